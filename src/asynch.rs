@@ -0,0 +1,674 @@
+use crate::error;
+use crate::request;
+use crate::timer;
+use crate::transport;
+use crate::types;
+use core::future::Future;
+use core::marker;
+use core::time;
+
+mod handler;
+
+#[derive(Debug)]
+pub struct Wifi<T, R = NoReinit> {
+    handler: handler::Handler<T>,
+    led_init: bool,
+    reinit: R,
+    clock: Option<fn() -> time::Duration>,
+    poll_interval: time::Duration,
+}
+
+// Configures the module from a reinit hook, bypassing the reset handling
+#[derive(Debug)]
+pub struct Setup<'a, T> {
+    handler: &'a mut handler::Handler<T>,
+    led_init: &'a mut bool,
+    clock: Option<fn() -> time::Duration>,
+    poll_interval: time::Duration,
+}
+
+// Runs before the next command after the recovery has reset the module
+pub trait Reinit<T>
+where
+    T: transport::AsyncTransport,
+{
+    fn reinit(
+        &mut self,
+        setup: &mut Setup<'_, T>,
+    ) -> impl Future<Output = Result<(), error::Error<T::Error>>>;
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoReinit;
+
+#[derive(Debug)]
+pub struct Client<T, const N: usize = { crate::BUFFER_CAPACITY }> {
+    socket: types::Socket,
+    buffer: [u8; N],
+    buffer_start: usize,
+    buffer_end: usize,
+    phantom: marker::PhantomData<T>,
+}
+
+impl<T> Reinit<T> for NoReinit
+where
+    T: transport::AsyncTransport,
+{
+    async fn reinit(&mut self, _setup: &mut Setup<'_, T>) -> Result<(), error::Error<T::Error>> {
+        Ok(())
+    }
+}
+
+impl<T> Wifi<T>
+where
+    T: transport::AsyncTransport,
+{
+    pub fn new(transport: T) -> Self {
+        let handler = handler::Handler::new(transport);
+        let led_init = false;
+        let reinit = NoReinit;
+        let clock = None;
+        let poll_interval = crate::DEFAULT_POLL_INTERVAL;
+        Self {
+            handler,
+            led_init,
+            reinit,
+            clock,
+            poll_interval,
+        }
+    }
+}
+
+impl<T, R> Wifi<T, R>
+where
+    T: transport::AsyncTransport,
+    R: Reinit<T>,
+{
+    pub fn transport(&self) -> &T {
+        self.handler.transport()
+    }
+
+    // After a reset, the module has to be configured again, see `with_reinit_hook`
+    pub fn set_recovery(&mut self, recovery: types::RecoveryConfig) {
+        self.handler.set_recovery(recovery);
+    }
+
+    pub fn with_reinit_hook<H>(self, reinit: H) -> Wifi<T, H>
+    where
+        H: Reinit<T>,
+    {
+        Wifi {
+            handler: self.handler,
+            led_init: self.led_init,
+            reinit,
+            clock: self.clock,
+            poll_interval: self.poll_interval,
+        }
+    }
+
+    pub fn set_clock(&mut self, now: fn() -> time::Duration) {
+        self.clock = Some(now);
+        self.handler.transport_mut().set_clock(now);
+    }
+
+    pub fn set_poll_interval(&mut self, poll_interval: time::Duration) {
+        self.poll_interval = poll_interval;
+    }
+
+    fn max_data_len(&self) -> usize {
+        self.handler.transport().max_data_len()
+    }
+
+    async fn reinit_if_reset(&mut self) -> Result<(), error::Error<T::Error>> {
+        if self.handler.take_reset_pending() {
+            // The module lost its LED pin configuration too
+            self.led_init = false;
+            let mut setup = Setup {
+                handler: &mut self.handler,
+                led_init: &mut self.led_init,
+                clock: self.clock,
+                poll_interval: self.poll_interval,
+            };
+            self.reinit.reinit(&mut setup).await?;
+        }
+        Ok(())
+    }
+
+    async fn handler(&mut self) -> Result<&mut handler::Handler<T>, error::Error<T::Error>> {
+        self.reinit_if_reset().await?;
+        Ok(&mut self.handler)
+    }
+
+    async fn setup(&mut self) -> Result<Setup<'_, T>, error::Error<T::Error>> {
+        self.reinit_if_reset().await?;
+        Ok(Setup {
+            handler: &mut self.handler,
+            led_init: &mut self.led_init,
+            clock: self.clock,
+            poll_interval: self.poll_interval,
+        })
+    }
+
+    pub async fn get_firmware_version(
+        &mut self,
+    ) -> Result<arrayvec::ArrayVec<[u8; 16]>, error::Error<T::Error>> {
+        self.handler().await?.get_firmware_version().await
+    }
+
+    pub async fn set_led(&mut self, r: u8, g: u8, b: u8) -> Result<(), error::Error<T::Error>> {
+        self.setup().await?.set_led(r, g, b).await
+    }
+
+    pub async fn configure(
+        &mut self,
+        config: types::Config<'_>,
+        connect_timeout: Option<time::Duration>,
+    ) -> Result<(), error::Error<T::Error>> {
+        self.setup().await?.configure(config, connect_timeout).await
+    }
+
+    pub async fn disconnect(&mut self) -> Result<(), error::Error<T::Error>> {
+        self.setup().await?.disconnect().await
+    }
+
+    pub async fn await_connection_state(
+        &mut self,
+        connection_state: types::ConnectionState,
+        timeout: time::Duration,
+    ) -> Result<(), error::Error<T::Error>> {
+        self.setup()
+            .await?
+            .await_connection_state(connection_state, timeout)
+            .await
+    }
+
+    pub async fn scan_networks(
+        &mut self,
+    ) -> Result<arrayvec::ArrayVec<[types::ScannedNetwork; 16]>, error::Error<T::Error>> {
+        self.handler().await?.start_scan_networks().await?;
+        let ssids = self.handler().await?.get_scanned_networks().await?;
+
+        let mut networks = arrayvec::ArrayVec::new();
+        for (i, ssid) in ssids.into_iter().enumerate() {
+            let i = i as u8;
+            let handler = self.handler().await?;
+            let rssi = handler.get_scanned_network_rssi(i).await?;
+            let encryption_type = handler.get_scanned_network_encryption_type(i).await?;
+            let bssid = handler.get_scanned_network_bssid(i).await?;
+            let channel = handler.get_scanned_network_channel(i).await?;
+
+            networks.push(types::ScannedNetwork {
+                ssid,
+                rssi,
+                encryption_type,
+                bssid,
+                channel,
+            });
+        }
+
+        Ok(networks)
+    }
+
+    pub async fn ssid(&mut self) -> Result<arrayvec::ArrayVec<[u8; 32]>, error::Error<T::Error>> {
+        self.handler().await?.get_current_ssid().await
+    }
+
+    pub async fn bssid(&mut self) -> Result<arrayvec::ArrayVec<[u8; 6]>, error::Error<T::Error>> {
+        self.handler().await?.get_current_bssid().await
+    }
+
+    pub async fn rssi(&mut self) -> Result<i32, error::Error<T::Error>> {
+        self.handler().await?.get_current_rssi().await
+    }
+
+    pub async fn encryption_type(
+        &mut self,
+    ) -> Result<types::EncryptionType, error::Error<T::Error>> {
+        self.handler().await?.get_current_encryption_type().await
+    }
+
+    pub async fn resolve(
+        &mut self,
+        hostname: &str,
+    ) -> Result<no_std_net::Ipv4Addr, error::Error<T::Error>> {
+        self.handler().await?.request_host_by_name(hostname).await?;
+        self.handler().await?.get_host_by_name().await
+    }
+
+    pub async fn set_ip_config(
+        &mut self,
+        ip_config: &types::IpConfig,
+    ) -> Result<(), error::Error<T::Error>> {
+        self.setup().await?.set_ip_config(ip_config).await
+    }
+
+    pub async fn mac_address(&mut self) -> Result<types::MacAddress, error::Error<T::Error>> {
+        self.handler().await?.get_mac_address().await
+    }
+
+    // The hostname must be a DNS label of at most 32 characters
+    pub async fn set_hostname(&mut self, hostname: &str) -> Result<(), error::Error<T::Error>> {
+        self.setup().await?.set_hostname(hostname).await
+    }
+
+    pub async fn network_data(&mut self) -> Result<types::NetworkData, error::Error<T::Error>> {
+        self.handler().await?.get_network_data().await
+    }
+
+    // Used by all later TLS connections
+    pub async fn set_client_certificate(
+        &mut self,
+        cert: &[u8],
+        key: &[u8],
+    ) -> Result<(), error::Error<T::Error>> {
+        self.setup().await?.set_client_certificate(cert, key).await
+    }
+
+    pub async fn new_client(&mut self) -> Result<Client<T>, error::Error<T::Error>> {
+        self.new_client_with_buffer().await
+    }
+
+    pub async fn new_client_with_buffer<const N: usize>(
+        &mut self,
+    ) -> Result<Client<T, N>, error::Error<T::Error>> {
+        let socket = self.handler().await?.get_socket().await?;
+        Ok(Client::new(socket))
+    }
+}
+
+impl<T> Setup<'_, T>
+where
+    T: transport::AsyncTransport,
+{
+    pub async fn set_led(&mut self, r: u8, g: u8, b: u8) -> Result<(), error::Error<T::Error>> {
+        if !*self.led_init {
+            for &pin in &request::LED_PINS {
+                self.handler.pin_mode(pin, types::PinMode::Output).await?;
+            }
+            *self.led_init = true;
+        }
+
+        for (&pin, &value) in request::LED_PINS.iter().zip(&[r, g, b]) {
+            self.handler.analog_write(pin, value).await?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn configure(
+        &mut self,
+        config: types::Config<'_>,
+        connect_timeout: Option<time::Duration>,
+    ) -> Result<(), error::Error<T::Error>> {
+        if let types::Config::Station(station_config) = &config {
+            if let Some(ip_config) = station_config.ip_config {
+                self.set_ip_config(&ip_config).await?;
+            }
+        }
+
+        self.handler.join_network(&config).await?;
+
+        if let Some(connect_timeout) = connect_timeout {
+            self.await_connection_state_matching(|state| config.is_up(state), connect_timeout)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn disconnect(&mut self) -> Result<(), error::Error<T::Error>> {
+        self.handler.disconnect().await
+    }
+
+    pub async fn await_connection_state(
+        &mut self,
+        connection_state: types::ConnectionState,
+        timeout: time::Duration,
+    ) -> Result<(), error::Error<T::Error>> {
+        self.await_connection_state_matching(|state| state == connection_state, timeout)
+            .await
+    }
+
+    async fn await_connection_state_matching(
+        &mut self,
+        mut predicate: impl FnMut(types::ConnectionState) -> bool,
+        timeout: time::Duration,
+    ) -> Result<(), error::Error<T::Error>> {
+        let mut timer = timer::Timer::start(self.clock);
+
+        let mut actual_connection_state;
+        loop {
+            actual_connection_state = self.handler.get_connection_state().await?;
+            if predicate(actual_connection_state) {
+                return Ok(());
+            }
+            if actual_connection_state == types::ConnectionState::ApFailed {
+                break;
+            }
+
+            self.handler.delay(self.poll_interval).await?;
+            timer.add_delay(self.poll_interval);
+
+            if timer.elapsed() > timeout {
+                break;
+            }
+        }
+
+        Err(error::Error::ConnectionFailure(actual_connection_state))
+    }
+
+    pub async fn set_ip_config(
+        &mut self,
+        ip_config: &types::IpConfig,
+    ) -> Result<(), error::Error<T::Error>> {
+        self.handler.set_ip_config(ip_config).await
+    }
+
+    pub async fn set_hostname(&mut self, hostname: &str) -> Result<(), error::Error<T::Error>> {
+        if !crate::is_valid_hostname(hostname) {
+            return Err(error::Error::InvalidHostname);
+        }
+
+        self.handler.set_hostname(hostname).await
+    }
+
+    pub async fn set_client_certificate(
+        &mut self,
+        cert: &[u8],
+        key: &[u8],
+    ) -> Result<(), error::Error<T::Error>> {
+        if cert.len() > crate::CLIENT_CERT_CAPACITY || key.len() > crate::CERT_KEY_CAPACITY {
+            return Err(error::Error::DataTooLong);
+        }
+
+        self.handler.set_client_cert(cert).await?;
+        self.handler.set_cert_key(key).await
+    }
+}
+
+impl<T, const N: usize> Client<T, N>
+where
+    T: transport::AsyncTransport,
+{
+    fn new(socket: types::Socket) -> Self {
+        let buffer = [0; N];
+        let buffer_start = 0;
+        let buffer_end = 0;
+        let phantom = marker::PhantomData;
+        Client {
+            socket,
+            buffer,
+            buffer_start,
+            buffer_end,
+            phantom,
+        }
+    }
+
+    pub async fn connect_ipv4(
+        &mut self,
+        wifi: &mut Wifi<T, impl Reinit<T>>,
+        ip: no_std_net::Ipv4Addr,
+        port: u16,
+        protocol_mode: types::ProtocolMode,
+    ) -> Result<(), error::Error<T::Error>> {
+        wifi.handler()
+            .await?
+            .start_client_by_ip(ip, port, self.socket, protocol_mode)
+            .await
+    }
+
+    pub async fn connect_hostname(
+        &mut self,
+        wifi: &mut Wifi<T, impl Reinit<T>>,
+        hostname: &str,
+        port: u16,
+        protocol_mode: types::ProtocolMode,
+    ) -> Result<(), error::Error<T::Error>> {
+        wifi.handler()
+            .await?
+            .start_client_by_hostname(hostname, port, self.socket, protocol_mode)
+            .await
+    }
+
+    pub async fn connect_tls(
+        &mut self,
+        wifi: &mut Wifi<T, impl Reinit<T>>,
+        hostname: &str,
+        port: u16,
+    ) -> Result<(), error::Error<T::Error>> {
+        self.connect_hostname(wifi, hostname, port, types::ProtocolMode::Tls)
+            .await
+    }
+
+    pub async fn send(
+        &mut self,
+        wifi: &mut Wifi<T, impl Reinit<T>>,
+        data: &[u8],
+    ) -> Result<usize, error::Error<T::Error>> {
+        let len = data.len().min(wifi.max_data_len());
+        let sent = wifi
+            .handler()
+            .await?
+            .send_data(self.socket, &data[..len])
+            .await?;
+        wifi.handler().await?.check_data_sent(self.socket).await?;
+        Ok(sent)
+    }
+
+    pub async fn send_all(
+        &mut self,
+        wifi: &mut Wifi<T, impl Reinit<T>>,
+        mut data: &[u8],
+    ) -> Result<(), error::Error<T::Error>> {
+        while !data.is_empty() {
+            let len = self.send(wifi, data).await?;
+            data = &data[len..];
+        }
+        Ok(())
+    }
+
+    pub async fn state(
+        &mut self,
+        wifi: &mut Wifi<T, impl Reinit<T>>,
+    ) -> Result<types::TcpState, error::Error<T::Error>> {
+        wifi.handler().await?.get_client_state(self.socket).await
+    }
+
+    pub fn socket(&self) -> types::Socket {
+        self.socket
+    }
+
+    pub async fn close(
+        self,
+        wifi: &mut Wifi<T, impl Reinit<T>>,
+    ) -> Result<(), error::Error<T::Error>> {
+        wifi.handler().await?.stop_client(self.socket).await
+    }
+
+    pub async fn available(
+        &mut self,
+        wifi: &mut Wifi<T, impl Reinit<T>>,
+    ) -> Result<usize, error::Error<T::Error>> {
+        let buffered = self.buffer_end - self.buffer_start;
+        let pending = wifi.handler().await?.avail_data(self.socket).await?;
+        Ok(buffered + usize::from(pending))
+    }
+
+    pub async fn recv(
+        &mut self,
+        wifi: &mut Wifi<T, impl Reinit<T>>,
+        data: &mut [u8],
+    ) -> Result<usize, error::Error<T::Error>> {
+        if self.buffer_start >= self.buffer_end {
+            // A slice at least as large as the buffer is filled directly, skipping the copy;
+            // without a buffer, this is always the case
+            if data.len() >= N {
+                let len = data.len().min(wifi.max_data_len());
+                return wifi
+                    .handler()
+                    .await?
+                    .get_data_buf(self.socket, &mut data[..len])
+                    .await;
+            }
+
+            let len = N.min(wifi.max_data_len());
+            self.buffer_end = wifi
+                .handler()
+                .await?
+                .get_data_buf(self.socket, &mut self.buffer[..len])
+                .await?;
+            self.buffer_start = 0;
+            log::debug!("fetched new buffer of len {}", self.buffer_end);
+        }
+
+        let len = data.len().min(self.buffer_end - self.buffer_start);
+        data[..len].copy_from_slice(&self.buffer[self.buffer_start..self.buffer_start + len]);
+        self.buffer_start += len;
+        Ok(len)
+    }
+
+    pub async fn recv_exact(
+        &mut self,
+        wifi: &mut Wifi<T, impl Reinit<T>>,
+        mut data: &mut [u8],
+    ) -> Result<(), error::Error<T::Error>> {
+        while !data.is_empty() {
+            let len = self.recv(wifi, data).await?;
+            data = &mut data[len..];
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::{Reinit, Setup, Wifi};
+    use crate::command::Command;
+    use crate::error::Error;
+    use crate::transport::{MockError, MockTransport};
+    use crate::types;
+    use core::future::Future;
+    use core::task;
+    use std::sync::Arc;
+    use std::vec;
+
+    struct NoopWaker;
+
+    impl std::task::Wake for NoopWaker {
+        fn wake(self: Arc<Self>) {}
+    }
+
+    // Polls a future once, as every mock command completes immediately
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let waker = task::Waker::from(Arc::new(NoopWaker));
+        let mut context = task::Context::from_waker(&waker);
+        let mut future = core::pin::pin!(future);
+        match future.as_mut().poll(&mut context) {
+            task::Poll::Ready(output) => output,
+            task::Poll::Pending => panic!("mock future is pending"),
+        }
+    }
+
+    #[test]
+    fn async_client_send_and_close() {
+        let mut mock = MockTransport::new();
+        mock.set_max_data_len(4)
+            .expect(Command::GetSocketCmd, &[&[2]])
+            .expect(Command::SendDataTcpCmd, &[&4u16.to_le_bytes()])
+            .expect(Command::DataSentTcpCmd, &[&[1]])
+            .expect(Command::StopClientTcpCmd, &[&[1]]);
+        let mut wifi = Wifi::new(mock);
+
+        block_on(async {
+            let mut client = wifi.new_client().await.unwrap();
+            assert_eq!(client.send(&mut wifi, b"abcdefgh").await, Ok(4));
+            client.close(&mut wifi).await.unwrap();
+        });
+
+        let calls = wifi.transport().calls();
+        assert_eq!(calls[1].params(), vec![&[2][..], &b"abcd"[..]]);
+        assert_eq!(calls[3].params(), vec![&[2][..]]);
+        assert_eq!(wifi.transport().pending(), 0);
+    }
+
+    #[test]
+    fn async_recovery() {
+        let mut mock = MockTransport::new();
+        mock.expect_error(Command::GetCurrRssiCmd, MockError::Desync)
+            .expect(Command::GetCurrRssiCmd, &[&(-50i32).to_be_bytes()])
+            .expect_error(Command::GetCurrRssiCmd, MockError::Injected)
+            .expect_error(Command::GetCurrRssiCmd, MockError::Injected);
+        let mut wifi = Wifi::new(mock);
+        wifi.set_recovery(types::RecoveryConfig {
+            retries: 1,
+            reset_after: Some(2),
+        });
+
+        block_on(async {
+            assert_eq!(wifi.rssi().await, Ok(-50));
+            assert_eq!(
+                wifi.rssi().await,
+                Err(Error::Transport(MockError::Injected))
+            );
+            assert_eq!(
+                wifi.rssi().await,
+                Err(Error::Transport(MockError::Injected))
+            );
+        });
+
+        assert_eq!(wifi.transport().resyncs(), 1);
+        assert_eq!(wifi.transport().resets(), 1);
+        assert_eq!(wifi.transport().pending(), 0);
+    }
+
+    struct RestoreLed;
+
+    impl Reinit<MockTransport> for RestoreLed {
+        async fn reinit(
+            &mut self,
+            setup: &mut Setup<'_, MockTransport>,
+        ) -> Result<(), Error<MockError>> {
+            setup.set_led(0, 0, 255).await
+        }
+    }
+
+    #[test]
+    fn async_reinit_after_reset() {
+        let mut mock = MockTransport::new();
+        mock.expect(Command::SetPinMode, &[&[1]])
+            .expect(Command::SetPinMode, &[&[1]])
+            .expect(Command::SetPinMode, &[&[1]])
+            .expect(Command::SetAnalogWrite, &[&[1]])
+            .expect(Command::SetAnalogWrite, &[&[1]])
+            .expect(Command::SetAnalogWrite, &[&[1]])
+            .expect_error(Command::GetCurrRssiCmd, MockError::Injected)
+            .expect(Command::SetPinMode, &[&[1]])
+            .expect(Command::SetPinMode, &[&[1]])
+            .expect(Command::SetPinMode, &[&[1]])
+            .expect(Command::SetAnalogWrite, &[&[1]])
+            .expect(Command::SetAnalogWrite, &[&[1]])
+            .expect(Command::SetAnalogWrite, &[&[1]])
+            .expect(Command::GetCurrRssiCmd, &[&(-50i32).to_be_bytes()]);
+        let mut wifi = Wifi::new(mock).with_reinit_hook(RestoreLed);
+        wifi.set_recovery(types::RecoveryConfig {
+            retries: 0,
+            reset_after: Some(1),
+        });
+
+        block_on(async {
+            wifi.set_led(255, 0, 0).await.unwrap();
+            assert_eq!(
+                wifi.rssi().await,
+                Err(Error::Transport(MockError::Injected))
+            );
+            // The hook runs before the next command, with the LED pins set up again
+            assert_eq!(wifi.rssi().await, Ok(-50));
+        });
+
+        let calls = wifi.transport().calls();
+        assert_eq!(calls[10].params(), vec![&[25][..], &[0][..]]);
+        assert_eq!(calls[12].params(), vec![&[27][..], &[255][..]]);
+        assert_eq!(wifi.transport().resets(), 1);
+        assert_eq!(wifi.transport().pending(), 0);
+    }
+}
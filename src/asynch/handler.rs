@@ -0,0 +1,433 @@
+#![allow(dead_code)]
+
+use crate::command;
+use crate::error;
+use crate::params;
+use crate::recovery;
+use crate::request;
+use crate::transport;
+use crate::types;
+use core::fmt;
+use core::time;
+
+#[derive(Debug)]
+pub struct Handler<T> {
+    transport: T,
+    recovery: recovery::Recovery,
+    reset_pending: bool,
+}
+
+impl<T> Handler<T>
+where
+    T: transport::AsyncTransport,
+{
+    pub fn new(transport: T) -> Self {
+        let recovery = recovery::Recovery::default();
+        let reset_pending = false;
+        Self {
+            transport,
+            recovery,
+            reset_pending,
+        }
+    }
+
+    pub fn set_recovery(&mut self, recovery: types::RecoveryConfig) {
+        self.recovery.set_config(recovery);
+    }
+
+    // Whether the module was reset to recover from failures since the last call
+    pub fn take_reset_pending(&mut self) -> bool {
+        core::mem::take(&mut self.reset_pending)
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    pub fn transport_mut(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    pub async fn get_connection_state(
+        &mut self,
+    ) -> Result<types::ConnectionState, error::Error<T::Error>> {
+        self.run(request::get_connection_state()).await
+    }
+
+    pub async fn delay(&mut self, duration: time::Duration) -> Result<(), error::Error<T::Error>> {
+        self.transport
+            .delay(duration)
+            .await
+            .map_err(error::Error::Transport)
+    }
+
+    pub async fn get_firmware_version(
+        &mut self,
+    ) -> Result<arrayvec::ArrayVec<[u8; 16]>, error::Error<T::Error>> {
+        self.run(request::get_firmware_version()).await
+    }
+
+    pub async fn get_mac_address(&mut self) -> Result<types::MacAddress, error::Error<T::Error>> {
+        self.run(request::get_mac_address()).await
+    }
+
+    pub async fn start_scan_networks(&mut self) -> Result<(), error::Error<T::Error>> {
+        self.run(request::start_scan_networks()).await
+    }
+
+    pub async fn get_scanned_networks(
+        &mut self,
+    ) -> Result<arrayvec::ArrayVec<[arrayvec::ArrayVec<[u8; 32]>; 16]>, error::Error<T::Error>>
+    {
+        self.run(request::get_scanned_networks()).await
+    }
+
+    pub async fn get_scanned_network_rssi(
+        &mut self,
+        network: u8,
+    ) -> Result<i32, error::Error<T::Error>> {
+        self.run(request::get_scanned_network_rssi(network)).await
+    }
+
+    pub async fn get_scanned_network_encryption_type(
+        &mut self,
+        network: u8,
+    ) -> Result<types::EncryptionType, error::Error<T::Error>> {
+        self.run(request::get_scanned_network_encryption_type(network))
+            .await
+    }
+
+    pub async fn get_scanned_network_bssid(
+        &mut self,
+        network: u8,
+    ) -> Result<[u8; 6], error::Error<T::Error>> {
+        self.run(request::get_scanned_network_bssid(network)).await
+    }
+
+    pub async fn get_scanned_network_channel(
+        &mut self,
+        network: u8,
+    ) -> Result<u8, error::Error<T::Error>> {
+        self.run(request::get_scanned_network_channel(network))
+            .await
+    }
+
+    pub async fn request_host_by_name(
+        &mut self,
+        hostname: &str,
+    ) -> Result<(), error::Error<T::Error>> {
+        self.run(request::request_host_by_name(hostname)).await
+    }
+
+    pub async fn get_host_by_name(
+        &mut self,
+    ) -> Result<no_std_net::Ipv4Addr, error::Error<T::Error>> {
+        self.run(request::get_host_by_name()).await
+    }
+
+    pub async fn get_network_data(&mut self) -> Result<types::NetworkData, error::Error<T::Error>> {
+        self.run(request::get_network_data()).await
+    }
+
+    pub async fn get_remote_data(
+        &mut self,
+        socket: types::Socket,
+    ) -> Result<types::RemoteData, error::Error<T::Error>> {
+        self.run(request::get_remote_data(socket)).await
+    }
+
+    pub async fn join_network(
+        &mut self,
+        config: &types::Config<'_>,
+    ) -> Result<(), error::Error<T::Error>> {
+        self.run(request::join_network(config)).await
+    }
+
+    pub async fn set_key(
+        &mut self,
+        ssid: &str,
+        key_idx: u8,
+        key: &[u8],
+    ) -> Result<(), error::Error<T::Error>> {
+        self.run(request::set_key(ssid, key_idx, key)).await
+    }
+
+    pub async fn set_client_cert(&mut self, cert: &[u8]) -> Result<(), error::Error<T::Error>> {
+        self.run(request::set_client_cert(cert)).await
+    }
+
+    pub async fn set_cert_key(&mut self, key: &[u8]) -> Result<(), error::Error<T::Error>> {
+        self.run(request::set_cert_key(key)).await
+    }
+
+    pub async fn set_ip_config(
+        &mut self,
+        ip_config: &types::IpConfig,
+    ) -> Result<(), error::Error<T::Error>> {
+        let (config, dns) = request::ip_config(ip_config);
+        self.run(config).await?;
+        if let Some(dns) = dns {
+            self.run(dns).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn set_hostname(&mut self, hostname: &str) -> Result<(), error::Error<T::Error>> {
+        self.run(request::set_hostname(hostname)).await
+    }
+
+    pub async fn disconnect(&mut self) -> Result<(), error::Error<T::Error>> {
+        self.run(request::disconnect()).await
+    }
+
+    pub async fn get_current_ssid(
+        &mut self,
+    ) -> Result<arrayvec::ArrayVec<[u8; 32]>, error::Error<T::Error>> {
+        self.run(request::get_current_ssid()).await
+    }
+
+    pub async fn get_current_bssid(
+        &mut self,
+    ) -> Result<arrayvec::ArrayVec<[u8; 6]>, error::Error<T::Error>> {
+        self.run(request::get_current_bssid()).await
+    }
+
+    pub async fn get_current_rssi(&mut self) -> Result<i32, error::Error<T::Error>> {
+        self.run(request::get_current_rssi()).await
+    }
+
+    pub async fn get_current_encryption_type(
+        &mut self,
+    ) -> Result<types::EncryptionType, error::Error<T::Error>> {
+        self.run(request::get_current_encryption_type()).await
+    }
+
+    pub async fn start_client_by_ip(
+        &mut self,
+        ip: no_std_net::Ipv4Addr,
+        port: u16,
+        socket: types::Socket,
+        protocol_mode: types::ProtocolMode,
+    ) -> Result<(), error::Error<T::Error>> {
+        self.run(request::start_client_by_ip(ip, port, socket, protocol_mode))
+            .await
+    }
+
+    pub async fn start_client_by_hostname(
+        &mut self,
+        hostname: &str,
+        port: u16,
+        socket: types::Socket,
+        protocol_mode: types::ProtocolMode,
+    ) -> Result<(), error::Error<T::Error>> {
+        self.run(request::start_client_by_hostname(
+            hostname,
+            port,
+            socket,
+            protocol_mode,
+        ))
+        .await
+    }
+
+    pub async fn start_server(
+        &mut self,
+        port: u16,
+        socket: types::Socket,
+        protocol_mode: types::ProtocolMode,
+    ) -> Result<(), error::Error<T::Error>> {
+        self.run(request::start_server(port, socket, protocol_mode))
+            .await
+    }
+
+    pub async fn start_server_multicast(
+        &mut self,
+        group: no_std_net::Ipv4Addr,
+        port: u16,
+        socket: types::Socket,
+    ) -> Result<(), error::Error<T::Error>> {
+        self.run(request::start_server_multicast(group, port, socket))
+            .await
+    }
+
+    pub async fn get_server_state(
+        &mut self,
+        socket: types::Socket,
+    ) -> Result<types::TcpState, error::Error<T::Error>> {
+        self.run(request::get_server_state(socket)).await
+    }
+
+    pub async fn avail_server(
+        &mut self,
+        socket: types::Socket,
+    ) -> Result<Option<types::Socket>, error::Error<T::Error>> {
+        // For listening sockets, the firmware reports the socket of an accepted client instead of
+        // the number of available bytes, or 255 if there is no pending connection.
+        match self.avail_data(socket).await? {
+            255 => Ok(None),
+            client => Ok(Some(types::Socket(client as u8))),
+        }
+    }
+
+    pub async fn stop_client(
+        &mut self,
+        socket: types::Socket,
+    ) -> Result<(), error::Error<T::Error>> {
+        self.run(request::stop_client(socket)).await
+    }
+
+    pub async fn get_client_state(
+        &mut self,
+        socket: types::Socket,
+    ) -> Result<types::TcpState, error::Error<T::Error>> {
+        self.run(request::get_client_state(socket)).await
+    }
+
+    pub async fn avail_data(
+        &mut self,
+        socket: types::Socket,
+    ) -> Result<u16, error::Error<T::Error>> {
+        self.run(request::avail_data(socket)).await
+    }
+
+    pub async fn get_data_buf(
+        &mut self,
+        socket: types::Socket,
+        buf: &mut [u8],
+    ) -> Result<usize, error::Error<T::Error>> {
+        self.run(request::get_data_buf(socket, buf)).await
+    }
+
+    pub async fn send_data(
+        &mut self,
+        socket: types::Socket,
+        data: &[u8],
+    ) -> Result<usize, error::Error<T::Error>> {
+        self.run(request::send_data(socket, data)).await
+    }
+
+    pub async fn check_data_sent(
+        &mut self,
+        socket: types::Socket,
+    ) -> Result<(), error::Error<T::Error>> {
+        self.run(request::check_data_sent(socket)).await
+    }
+
+    pub async fn insert_data_buf(
+        &mut self,
+        socket: types::Socket,
+        data: &[u8],
+    ) -> Result<(), error::Error<T::Error>> {
+        self.run(request::insert_data_buf(socket, data)).await
+    }
+
+    pub async fn send_udp_data(
+        &mut self,
+        socket: types::Socket,
+    ) -> Result<(), error::Error<T::Error>> {
+        self.run(request::send_udp_data(socket)).await
+    }
+
+    pub async fn get_socket(&mut self) -> Result<types::Socket, error::Error<T::Error>> {
+        self.run(request::get_socket()).await
+    }
+
+    pub async fn pin_mode(
+        &mut self,
+        pin: u8,
+        mode: types::PinMode,
+    ) -> Result<(), error::Error<T::Error>> {
+        self.run(request::pin_mode(pin, mode)).await
+    }
+
+    pub async fn digital_write(
+        &mut self,
+        pin: u8,
+        value: u8,
+    ) -> Result<(), error::Error<T::Error>> {
+        self.run(request::digital_write(pin, value)).await
+    }
+
+    pub async fn analog_write(&mut self, pin: u8, value: u8) -> Result<(), error::Error<T::Error>> {
+        self.run(request::analog_write(pin, value)).await
+    }
+
+    async fn run<SP, RP, O>(
+        &mut self,
+        mut request: request::Request<SP, RP, O, T::Error>,
+    ) -> Result<O, error::Error<T::Error>>
+    where
+        SP: params::SendParams + fmt::Debug,
+        RP: params::RecvParams + fmt::Debug,
+    {
+        self.handle_cmd_recovering(
+            request.command,
+            &request.send_params,
+            &mut request.recv_params,
+            request.long_send,
+            request.long_recv,
+        )
+        .await?;
+        request.finish()
+    }
+
+    fn transport_error(error: T::Error) -> error::Error<T::Error> {
+        match T::protocol_error(&error) {
+            Some(error) => error::Error::Protocol(error),
+            None => error::Error::Transport(error),
+        }
+    }
+
+    async fn handle_cmd_recovering<SP, RP>(
+        &mut self,
+        command: command::Command,
+        send_params: &SP,
+        recv_params: &mut RP,
+        long_send: bool,
+        long_recv: bool,
+    ) -> Result<(), error::Error<T::Error>>
+    where
+        SP: params::SendParams + fmt::Debug,
+        RP: params::RecvParams + fmt::Debug,
+    {
+        send_params
+            .check(long_send)
+            .map_err(error::Error::Protocol)?;
+
+        let mut retries = 0;
+        loop {
+            let error = match self
+                .transport
+                .handle_cmd(command, send_params, recv_params, long_send, long_recv)
+                .await
+            {
+                Ok(()) => {
+                    self.recovery.succeeded();
+                    return Ok(());
+                }
+                Err(error) => error,
+            };
+
+            let desync = T::is_desync(&error);
+            if desync {
+                log::debug!("resync after {:?} failed", command);
+                self.transport
+                    .resync()
+                    .await
+                    .map_err(error::Error::Transport)?;
+            }
+
+            match self.recovery.failed(command, desync, retries) {
+                recovery::Action::Retry => retries += 1,
+                recovery::Action::Reset => {
+                    self.transport
+                        .reset()
+                        .await
+                        .map_err(error::Error::Transport)?;
+                    self.reset_pending = true;
+                    return Err(Self::transport_error(error));
+                }
+                recovery::Action::Fail => return Err(Self::transport_error(error)),
+            }
+        }
+    }
+}
@@ -45,6 +45,8 @@ pub enum Command {
     GetSocketCmd = 0x3F,
 
     // All command with DATA_FLAG 0x40 send a 16bit Len
+    SetClientCertCmd = 0x40,
+    SetCertKeyCmd = 0x41,
     SendDataTcpCmd = 0x44,
     GetDatabufTcpCmd = 0x45,
     InsertDatabufCmd = 0x46,
@@ -54,3 +56,32 @@ pub enum Command {
     SetDigitalWrite = 0x51,
     SetAnalogWrite = 0x52,
 }
+
+impl Command {
+    // Whether sending the command again has no further effect on the module, so that it can be
+    // retried when its reply was lost
+    pub fn is_idempotent(self) -> bool {
+        matches!(
+            self,
+            Command::GetTemperatureCmd
+                | Command::GetConnStatusCmd
+                | Command::GetIpaddrCmd
+                | Command::GetMacaddrCmd
+                | Command::GetCurrSsidCmd
+                | Command::GetCurrBssidCmd
+                | Command::GetCurrRssiCmd
+                | Command::GetCurrEnctCmd
+                | Command::ScanNetworks
+                | Command::GetStateTcpCmd
+                | Command::GetClientStateTcpCmd
+                | Command::GetIdxRssiCmd
+                | Command::GetIdxEnctCmd
+                | Command::GetHostByNameCmd
+                | Command::GetFwVersionCmd
+                | Command::GetRemoteDataCmd
+                | Command::GetTimeCmd
+                | Command::GetIdxBssid
+                | Command::GetIdxChannelCmd
+        )
+    }
+}
@@ -1,39 +1,92 @@
-pub fn recv_len<S>(spi: &mut S, long: bool) -> Result<usize, S::Error>
+use crate::error;
+
+pub trait Sink {
+    type Error: From<error::ProtocolError>;
+
+    fn send_byte(&mut self, byte: u8) -> Result<(), Self::Error>;
+}
+
+pub trait Source {
+    type Error: From<error::ProtocolError>;
+
+    fn recv_byte(&mut self) -> Result<u8, Self::Error>;
+}
+
+#[cfg(feature = "std")]
+pub struct VecSink<'a>(pub &'a mut std::vec::Vec<u8>);
+
+// Discards everything sent to it, for checking that params can be encoded before sending them
+pub struct CheckSink;
+
+#[cfg(feature = "std")]
+impl Sink for VecSink<'_> {
+    type Error = error::ProtocolError;
+
+    fn send_byte(&mut self, byte: u8) -> Result<(), Self::Error> {
+        self.0.push(byte);
+        Ok(())
+    }
+}
+
+impl Sink for CheckSink {
+    type Error = error::ProtocolError;
+
+    fn send_byte(&mut self, _byte: u8) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LinkError<E> {
+    Link(E),
+    Protocol(error::ProtocolError),
+}
+
+impl<E> From<error::ProtocolError> for LinkError<E> {
+    fn from(error: error::ProtocolError) -> Self {
+        LinkError::Protocol(error)
+    }
+}
+
+pub fn recv_len<S>(source: &mut S, long: bool) -> Result<usize, S::Error>
 where
-    S: embedded_hal::spi::FullDuplex<u8>,
+    S: Source,
 {
-    use super::full_duplex::FullDuplexExt as _;
     use byteorder::ByteOrder as _;
 
     let len = if long {
         let mut buf = [0; 2];
-        buf[0] = spi.recv_exchange()?;
-        buf[1] = spi.recv_exchange()?;
+        buf[0] = source.recv_byte()?;
+        buf[1] = source.recv_byte()?;
         byteorder::BigEndian::read_u16(&buf) as usize
     } else {
-        spi.recv_exchange()? as usize
+        source.recv_byte()? as usize
     };
 
     Ok(len)
 }
 
-pub fn send_len<S>(spi: &mut S, long: bool, len: usize) -> Result<(), S::Error>
+pub fn send_len<S>(sink: &mut S, long: bool, len: usize) -> Result<(), S::Error>
 where
-    S: embedded_hal::spi::FullDuplex<u8>,
+    S: Sink,
 {
-    use super::full_duplex::FullDuplexExt as _;
     use byteorder::ByteOrder as _;
     use core::convert::TryFrom;
 
+    let overflow = |capacity: usize| error::ProtocolError::Overflow {
+        capacity,
+        actual: len,
+    };
+
     if long {
-        let len = u16::try_from(len).unwrap();
+        let len = u16::try_from(len).map_err(|_| overflow(u16::MAX as usize))?;
         let mut buf = [0; 2];
         byteorder::BigEndian::write_u16(&mut buf, len);
-        spi.send_exchange(buf[0])?;
-        spi.send_exchange(buf[1])?;
+        sink.send_byte(buf[0])?;
+        sink.send_byte(buf[1])?;
     } else {
-        let len = u8::try_from(len).unwrap();
-        spi.send_exchange(len)?;
+        let len = u8::try_from(len).map_err(|_| overflow(u8::MAX as usize))?;
+        sink.send_byte(len)?;
     }
 
     Ok(())
@@ -3,18 +3,27 @@ use crate::types;
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum Error<E> {
     Transport(E),
+    Protocol(ProtocolError),
     SetNetwork,
     SetPassphrase,
     SetKey,
     SetIpConfig,
     SetDnsConfig,
     SetHostname,
+    SetApNetwork,
+    SetApPassphrase,
+    SetClientCert,
+    SetCertKey,
     Disconnect,
     ReqHostByName,
     StartScanNetworks,
     StartClientByIp,
+    StartClientByHostname,
+    StartServer,
     StopClient,
     CheckDataSent,
+    InsertDataBuf,
+    SendUdpData,
     PinMode,
     DigitalWrite,
     AnalogWrite,
@@ -23,4 +32,21 @@ pub enum Error<E> {
     BadEncryptionType(num_enum::TryFromPrimitiveError<types::EncryptionType>),
     BadTcpState(num_enum::TryFromPrimitiveError<types::TcpState>),
     DataTooLong,
+    NotMulticast(no_std_net::Ipv4Addr),
+    InvalidHostname,
+    NotConnected,
+    Timeout,
+    ConnectionClosed,
+    NoFreeSocket,
+    SocketInUse(types::Socket),
+    Unsupported,
+}
+
+// Malformed data received from the module
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ProtocolError {
+    LengthMismatch { expected: usize, actual: usize },
+    Overflow { capacity: usize, actual: usize },
+    BadTerminator(u8),
+    BadParamCount { expected: usize, actual: usize },
 }
@@ -0,0 +1,299 @@
+#[cfg(feature = "embedded-hal-1")]
+use crate::command;
+#[cfg(any(feature = "std", feature = "embedded-hal-1"))]
+use crate::encoding;
+use crate::error;
+#[cfg(feature = "embedded-hal-1")]
+use crate::params;
+#[cfg(feature = "embedded-hal-1")]
+use core::fmt;
+
+pub const START_CMD: u8 = 0xe0;
+pub const END_CMD: u8 = 0xee;
+pub const ERR_CMD: u8 = 0xef;
+pub const REPLY_FLAG: u8 = 1 << 7;
+
+#[cfg(feature = "embedded-hal-1")]
+// Bytes that may precede the START_CMD of a reply when it is read as a whole frame
+pub const REPLY_SLACK_BYTES: usize = 8;
+
+#[cfg(feature = "embedded-hal-1")]
+// Bytes that a frame adds to the data it carries for a socket: the slack before a reply,
+// START_CMD, command, param count, the socket param, the data length, END_CMD and padding
+const DATA_OVERHEAD_BYTES: usize = 16;
+
+#[cfg(feature = "embedded-hal-1")]
+// Enough for a command or reply carrying a full client buffer of data
+pub const DEFAULT_FRAME_CAPACITY: usize = crate::BUFFER_CAPACITY + 32;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FrameError {
+    Overflow,
+    Timeout,
+    ErrorResponse,
+    UnexpectedReplyByte(u8),
+    Protocol(error::ProtocolError),
+}
+
+#[cfg(feature = "std")]
+// Iterates over the raw length-delimited params of a frame, starting at the param count
+#[derive(Clone, Debug)]
+pub struct RawParams<'a> {
+    buf: &'a [u8],
+    remaining: u8,
+    long: bool,
+}
+
+#[cfg(feature = "embedded-hal-1")]
+#[derive(Debug)]
+pub struct Writer<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+#[cfg(any(feature = "std", feature = "embedded-hal-1"))]
+#[derive(Debug)]
+pub struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+#[cfg(feature = "embedded-hal-1")]
+impl<'a> Writer<'a> {
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        let pos = 0;
+        Self { buf, pos }
+    }
+
+    pub fn len(&self) -> usize {
+        self.pos
+    }
+}
+
+#[cfg(feature = "embedded-hal-1")]
+impl encoding::Sink for Writer<'_> {
+    type Error = FrameError;
+
+    fn send_byte(&mut self, byte: u8) -> Result<(), Self::Error> {
+        let slot = self.buf.get_mut(self.pos).ok_or(FrameError::Overflow)?;
+        *slot = byte;
+        self.pos += 1;
+        Ok(())
+    }
+}
+
+#[cfg(any(feature = "std", feature = "embedded-hal-1"))]
+impl<'a> Reader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        let pos = 0;
+        Self { buf, pos }
+    }
+}
+
+#[cfg(any(feature = "std", feature = "embedded-hal-1"))]
+impl encoding::Source for Reader<'_> {
+    type Error = FrameError;
+
+    fn recv_byte(&mut self) -> Result<u8, Self::Error> {
+        let byte = *self.buf.get(self.pos).ok_or(FrameError::Overflow)?;
+        self.pos += 1;
+        Ok(byte)
+    }
+}
+
+impl From<error::ProtocolError> for FrameError {
+    fn from(error: error::ProtocolError) -> Self {
+        FrameError::Protocol(error)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<'a> RawParams<'a> {
+    pub fn new(buf: &'a [u8], long: bool) -> Result<Self, FrameError> {
+        let (&remaining, buf) = buf.split_first().ok_or(FrameError::Overflow)?;
+        Ok(Self {
+            buf,
+            remaining,
+            long,
+        })
+    }
+
+    // The bytes following the last param
+    pub fn rest(&self) -> &'a [u8] {
+        self.buf
+    }
+}
+
+#[cfg(feature = "std")]
+impl<'a> Iterator for RawParams<'a> {
+    type Item = Result<&'a [u8], FrameError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+
+        let mut reader = Reader::new(self.buf);
+        let param = encoding::recv_len(&mut reader, self.long).and_then(|len| {
+            let start = reader.pos;
+            let param = self
+                .buf
+                .get(start..start + len)
+                .ok_or(FrameError::Overflow)?;
+            self.buf = &self.buf[start + len..];
+            Ok(param)
+        });
+        if param.is_err() {
+            self.remaining = 0;
+        }
+
+        Some(param)
+    }
+}
+
+#[cfg(feature = "std")]
+pub fn encode_params<S, P>(sink: &mut S, params: &[P], long: bool) -> Result<(), S::Error>
+where
+    S: encoding::Sink,
+    P: AsRef<[u8]>,
+{
+    use crate::param::SendParam as _;
+
+    sink.send_byte(params.len() as u8)?;
+    for param in params {
+        param.as_ref().send_length_delimited(sink, long)?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "embedded-hal-1")]
+pub fn encode_cmd<SP>(
+    buf: &mut [u8],
+    command: command::Command,
+    send_params: &SP,
+    long_send: bool,
+) -> Result<usize, FrameError>
+where
+    SP: params::SendParams,
+{
+    use crate::encoding::Sink as _;
+
+    let mut writer = Writer::new(buf);
+    writer.send_byte(START_CMD)?;
+    writer.send_byte(u8::from(command) & !REPLY_FLAG)?;
+    send_params.send(&mut writer, long_send)?;
+    writer.send_byte(END_CMD)?;
+
+    // Pad to 4 byte boundary
+    while 0 != writer.len() % 4 {
+        writer.send_byte(0xff)?;
+    }
+
+    Ok(writer.len())
+}
+
+#[cfg(feature = "embedded-hal-1")]
+// Performs a command as two whole-frame transactions, reusing `buffer` for the command and the
+// reply frame
+pub fn handle_cmd<SP, RP, E>(
+    buffer: &mut [u8],
+    command: command::Command,
+    send_params: &SP,
+    recv_params: &mut RP,
+    long_send: bool,
+    long_recv: bool,
+    mut transaction: impl FnMut(&mut [u8]) -> Result<(), E>,
+) -> Result<(), E>
+where
+    SP: params::SendParams + fmt::Debug,
+    RP: params::RecvParams + fmt::Debug,
+    E: From<FrameError>,
+{
+    // Once the command is sent, the firmware commits to a reply that would be lost if it didn't fit
+    let reply_len = reply_capacity(recv_params, long_recv);
+    if reply_len > buffer.len() {
+        return Err(FrameError::Overflow.into());
+    }
+
+    let len = encode_cmd(buffer, command, send_params, long_send)?;
+    transaction(&mut buffer[..len])?;
+    log::debug!("send {:?} {:?}", command, send_params);
+
+    let reply = &mut buffer[..reply_len];
+    reply.fill(0);
+    transaction(reply)?;
+    decode_reply(reply, command, recv_params, long_recv)?;
+    log::debug!("recv {:?} {:?} -> {:?}", command, send_params, recv_params);
+
+    Ok(())
+}
+
+#[cfg(feature = "embedded-hal-1")]
+// Clocks out a whole frame, so that a reply that came too late for the previous command isn't
+// taken for the reply to the next one
+pub fn resync<E>(
+    buffer: &mut [u8],
+    transaction: impl FnOnce(&mut [u8]) -> Result<(), E>,
+) -> Result<(), E> {
+    buffer.fill(0);
+    transaction(buffer)
+}
+
+#[cfg(feature = "embedded-hal-1")]
+// The most socket data that a frame of `capacity` bytes can carry either way
+pub const fn max_data_len(capacity: usize) -> usize {
+    let len = capacity.saturating_sub(DATA_OVERHEAD_BYTES);
+    if len < u16::MAX as usize {
+        len
+    } else {
+        u16::MAX as usize
+    }
+}
+
+#[cfg(feature = "embedded-hal-1")]
+pub fn reply_capacity<RP>(recv_params: &RP, long_recv: bool) -> usize
+where
+    RP: params::RecvParams,
+{
+    // START_CMD, command and END_CMD around the params
+    REPLY_SLACK_BYTES + recv_params.capacity(long_recv) + 3
+}
+
+#[cfg(feature = "embedded-hal-1")]
+pub fn decode_reply<RP>(
+    buf: &[u8],
+    command: command::Command,
+    recv_params: &mut RP,
+    long_recv: bool,
+) -> Result<(), FrameError>
+where
+    RP: params::RecvParams,
+{
+    let start = buf
+        .iter()
+        .position(|&byte| byte == START_CMD || byte == ERR_CMD)
+        .ok_or(FrameError::Timeout)?;
+    if buf[start] == ERR_CMD {
+        return Err(FrameError::ErrorResponse);
+    }
+
+    let mut reader = Reader::new(&buf[start + 1..]);
+    expect_byte(&mut reader, u8::from(command) | REPLY_FLAG)?;
+    recv_params.recv(&mut reader, long_recv)?;
+    expect_byte(&mut reader, END_CMD)?;
+
+    Ok(())
+}
+
+#[cfg(feature = "embedded-hal-1")]
+fn expect_byte(reader: &mut Reader, expected_byte: u8) -> Result<(), FrameError> {
+    use crate::encoding::Source as _;
+
+    let byte = reader.recv_byte()?;
+    if byte == expected_byte {
+        Ok(())
+    } else {
+        Err(FrameError::UnexpectedReplyByte(byte))
+    }
+}
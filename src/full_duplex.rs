@@ -25,3 +25,29 @@ where
     Word: core::fmt::LowerHex + Copy + Default,
 {
 }
+
+impl<T> crate::encoding::Sink for T
+where
+    T: embedded_hal::spi::FullDuplex<u8>,
+{
+    type Error = crate::encoding::LinkError<T::Error>;
+
+    #[inline]
+    fn send_byte(&mut self, byte: u8) -> Result<(), Self::Error> {
+        self.send_exchange(byte)
+            .map_err(crate::encoding::LinkError::Link)
+    }
+}
+
+impl<T> crate::encoding::Source for T
+where
+    T: embedded_hal::spi::FullDuplex<u8>,
+{
+    type Error = crate::encoding::LinkError<T::Error>;
+
+    #[inline]
+    fn recv_byte(&mut self) -> Result<u8, Self::Error> {
+        self.recv_exchange()
+            .map_err(crate::encoding::LinkError::Link)
+    }
+}
@@ -2,8 +2,9 @@
 
 use crate::command;
 use crate::error;
-use crate::param;
 use crate::params;
+use crate::recovery;
+use crate::request;
 use crate::transport;
 use crate::types;
 use core::fmt;
@@ -12,6 +13,8 @@ use core::time;
 #[derive(Debug)]
 pub struct Handler<T> {
     transport: T,
+    recovery: recovery::Recovery,
+    reset_pending: bool,
 }
 
 impl<T> Handler<T>
@@ -19,23 +22,36 @@ where
     T: transport::Transport,
 {
     pub fn new(transport: T) -> Self {
-        Self { transport }
+        let recovery = recovery::Recovery::default();
+        let reset_pending = false;
+        Self {
+            transport,
+            recovery,
+            reset_pending,
+        }
     }
 
-    pub fn get_connection_state(
-        &mut self,
-    ) -> Result<types::ConnectionState, error::Error<T::Error>> {
-        use core::convert::TryFrom;
+    pub fn set_recovery(&mut self, recovery: types::RecoveryConfig) {
+        self.recovery.set_config(recovery);
+    }
 
-        let mut recv_params = (0u8,);
+    // Whether the module was reset to recover from failures since the last call
+    pub fn take_reset_pending(&mut self) -> bool {
+        core::mem::take(&mut self.reset_pending)
+    }
 
-        self.handle_cmd(command::Command::GetConnStatusCmd, &(), &mut recv_params)?;
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
 
-        let (status,) = recv_params;
-        let status =
-            types::ConnectionState::try_from(status).map_err(error::Error::BadConnectionStatus)?;
+    pub fn transport_mut(&mut self) -> &mut T {
+        &mut self.transport
+    }
 
-        Ok(status)
+    pub fn get_connection_state(
+        &mut self,
+    ) -> Result<types::ConnectionState, error::Error<T::Error>> {
+        self.run(request::get_connection_state())
     }
 
     pub fn delay(&mut self, duration: time::Duration) -> Result<(), error::Error<T::Error>> {
@@ -47,243 +63,73 @@ where
     pub fn get_firmware_version(
         &mut self,
     ) -> Result<arrayvec::ArrayVec<[u8; 16]>, error::Error<T::Error>> {
-        let send_params = (0u8,);
-        let mut recv_params = (param::NullTerminated::new(arrayvec::ArrayVec::new()),);
-
-        self.handle_cmd(
-            command::Command::GetFwVersionCmd,
-            &send_params,
-            &mut recv_params,
-        )?;
-
-        let result = recv_params.0.into_inner();
-
-        Ok(result)
+        self.run(request::get_firmware_version())
     }
 
-    pub fn get_mac_address(&mut self) -> Result<[u8; 6], error::Error<T::Error>> {
-        let send_params = (0u8,);
-        let mut recv_params = (arrayvec::ArrayVec::new(),);
-
-        self.handle_cmd(
-            command::Command::GetMacaddrCmd,
-            &send_params,
-            &mut recv_params,
-        )?;
-
-        Ok(recv_params.0.into_inner().unwrap())
+    pub fn get_mac_address(&mut self) -> Result<types::MacAddress, error::Error<T::Error>> {
+        self.run(request::get_mac_address())
     }
 
     pub fn start_scan_networks(&mut self) -> Result<(), error::Error<T::Error>> {
-        let mut recv_params = (0u8,);
-
-        self.handle_cmd(command::Command::StartScanNetworks, &(), &mut recv_params)?;
-
-        let (status,) = recv_params;
-
-        if status == 1 {
-            Ok(())
-        } else {
-            Err(error::Error::StartScanNetworks)
-        }
+        self.run(request::start_scan_networks())
     }
 
     pub fn get_scanned_networks(
         &mut self,
     ) -> Result<arrayvec::ArrayVec<[arrayvec::ArrayVec<[u8; 32]>; 16]>, error::Error<T::Error>>
     {
-        let mut recv_params: arrayvec::ArrayVec<[arrayvec::ArrayVec<[u8; 32]>; 16]> =
-            arrayvec::ArrayVec::new();
-
-        self.handle_cmd(command::Command::ScanNetworks, &(), &mut recv_params)?;
-
-        Ok(recv_params)
+        self.run(request::get_scanned_networks())
     }
 
     pub fn get_scanned_network_rssi(&mut self, network: u8) -> Result<i32, error::Error<T::Error>> {
-        let send_params = (network,);
-        let mut recv_params = (param::Scalar::le(0u32),);
-
-        self.handle_cmd(
-            command::Command::GetIdxRssiCmd,
-            &send_params,
-            &mut recv_params,
-        )?;
-
-        let (rssi,) = recv_params;
-
-        Ok(rssi.into_inner() as i32)
+        self.run(request::get_scanned_network_rssi(network))
     }
 
     pub fn get_scanned_network_encryption_type(
         &mut self,
         network: u8,
     ) -> Result<types::EncryptionType, error::Error<T::Error>> {
-        use core::convert::TryFrom;
-
-        let send_params = (network,);
-        let mut recv_params = (0u8,);
-
-        self.handle_cmd(
-            command::Command::GetIdxEnctCmd,
-            &send_params,
-            &mut recv_params,
-        )?;
-
-        let (encryption_type,) = recv_params;
-
-        let encryption_type = types::EncryptionType::try_from(encryption_type)
-            .map_err(error::Error::BadEncryptionType)?;
-
-        Ok(encryption_type)
+        self.run(request::get_scanned_network_encryption_type(network))
     }
 
     pub fn get_scanned_network_bssid(
         &mut self,
         network: u8,
     ) -> Result<[u8; 6], error::Error<T::Error>> {
-        let send_params = (network,);
-        let mut recv_params = (arrayvec::ArrayVec::new(),);
-
-        self.handle_cmd(
-            command::Command::GetIdxBssid,
-            &send_params,
-            &mut recv_params,
-        )?;
-
-        let (bssid,) = recv_params;
-
-        Ok(bssid.into_inner().unwrap())
+        self.run(request::get_scanned_network_bssid(network))
     }
 
     pub fn get_scanned_network_channel(
         &mut self,
         network: u8,
     ) -> Result<u8, error::Error<T::Error>> {
-        let send_params = (network,);
-        let mut recv_params = (0u8,);
-
-        self.handle_cmd(
-            command::Command::GetIdxChannelCmd,
-            &send_params,
-            &mut recv_params,
-        )?;
-
-        let (channel,) = recv_params;
-
-        Ok(channel)
+        self.run(request::get_scanned_network_channel(network))
     }
 
     pub fn request_host_by_name(&mut self, hostname: &str) -> Result<(), error::Error<T::Error>> {
-        let send_params = (param::NullTerminated::new(hostname.as_bytes()),);
-        let mut recv_params = (0u8,);
-
-        self.handle_cmd(
-            command::Command::ReqHostByNameCmd,
-            &send_params,
-            &mut recv_params,
-        )?;
-
-        let (status,) = recv_params;
-
-        if status == 1 {
-            Ok(())
-        } else {
-            Err(error::Error::ReqHostByName)
-        }
+        self.run(request::request_host_by_name(hostname))
     }
 
     pub fn get_host_by_name(&mut self) -> Result<no_std_net::Ipv4Addr, error::Error<T::Error>> {
-        let mut recv_params = (param::Scalar::be(0u32),);
-
-        self.handle_cmd(command::Command::GetHostByNameCmd, &(), &mut recv_params)?;
-
-        let (ip,) = recv_params;
-
-        Ok(ip.into_inner().into())
+        self.run(request::get_host_by_name())
     }
 
     pub fn get_network_data(&mut self) -> Result<types::NetworkData, error::Error<T::Error>> {
-        let send_params = (0u8,);
-        let mut recv_params = (
-            param::Scalar::be(0u32),
-            param::Scalar::be(0u32),
-            param::Scalar::be(0u32),
-        );
-
-        self.handle_cmd(
-            command::Command::GetIpaddrCmd,
-            &send_params,
-            &mut recv_params,
-        )?;
-
-        let (ip, mask, gateway) = recv_params;
-        let ip = ip.into_inner().into();
-        let mask = mask.into_inner().into();
-        let gateway = gateway.into_inner().into();
-
-        Ok(types::NetworkData { ip, mask, gateway })
+        self.run(request::get_network_data())
     }
 
     pub fn get_remote_data(
         &mut self,
         socket: types::Socket,
     ) -> Result<types::RemoteData, error::Error<T::Error>> {
-        let send_params = (socket.0,);
-        let mut recv_params = (param::Scalar::be(0u32), param::Scalar::be(0u32));
-
-        self.handle_cmd(
-            command::Command::GetRemoteDataCmd,
-            &send_params,
-            &mut recv_params,
-        )?;
-
-        let (ip, port) = recv_params;
-        let ip = ip.into_inner().into();
-        let port = port.into_inner();
-
-        Ok(types::RemoteData { ip, port })
-    }
-
-    pub fn set_network(&mut self, ssid: &[u8]) -> Result<(), error::Error<T::Error>> {
-        let send_params = (param::NullTerminated::new(ssid),);
-        let mut recv_params = (0u8,);
-
-        self.handle_cmd(command::Command::SetNetCmd, &send_params, &mut recv_params)?;
-
-        let (status,) = recv_params;
-
-        if status == 1 {
-            Ok(())
-        } else {
-            Err(error::Error::SetNetwork)
-        }
+        self.run(request::get_remote_data(socket))
     }
 
-    pub fn set_passphrase(
+    pub fn join_network(
         &mut self,
-        ssid: &[u8],
-        passphrase: &[u8],
+        config: &types::Config<'_>,
     ) -> Result<(), error::Error<T::Error>> {
-        let send_params = (
-            param::NullTerminated::new(ssid),
-            param::NullTerminated::new(passphrase),
-        );
-        let mut recv_params = (0u8,);
-
-        self.handle_cmd(
-            command::Command::SetPassphraseCmd,
-            &send_params,
-            &mut recv_params,
-        )?;
-
-        let (status,) = recv_params;
-
-        if status == 1 {
-            Ok(())
-        } else {
-            Err(error::Error::SetPassphrase)
-        }
+        self.run(request::join_network(config))
     }
 
     pub fn set_key(
@@ -292,188 +138,57 @@ where
         key_idx: u8,
         key: &[u8],
     ) -> Result<(), error::Error<T::Error>> {
-        let send_params = (
-            param::NullTerminated::new(ssid.as_bytes()),
-            key_idx,
-            // TODO: null terminate?
-            key,
-        );
-        let mut recv_params = (0u8,);
-
-        self.handle_cmd(command::Command::SetKeyCmd, &send_params, &mut recv_params)?;
-
-        let (status,) = recv_params;
-
-        if status == 1 {
-            Ok(())
-        } else {
-            Err(error::Error::SetKey)
-        }
+        self.run(request::set_key(ssid, key_idx, key))
     }
 
-    pub fn config(
-        &mut self,
-        valid_params: u8,
-        local_ip: no_std_net::Ipv4Addr,
-        gateway: no_std_net::Ipv4Addr,
-        subnet: no_std_net::Ipv4Addr,
-    ) -> Result<(), error::Error<T::Error>> {
-        let send_params = (
-            valid_params,
-            param::Scalar::be(u32::from(local_ip)),
-            param::Scalar::be(u32::from(gateway)),
-            param::Scalar::be(u32::from(subnet)),
-        );
-        let mut recv_params = (0u8,);
-
-        self.handle_cmd(
-            command::Command::SetIpConfigCmd,
-            &send_params,
-            &mut recv_params,
-        )?;
-
-        let (status,) = recv_params;
+    pub fn set_client_cert(&mut self, cert: &[u8]) -> Result<(), error::Error<T::Error>> {
+        self.run(request::set_client_cert(cert))
+    }
 
-        if status == 1 {
-            Ok(())
-        } else {
-            Err(error::Error::SetIpConfig)
-        }
+    pub fn set_cert_key(&mut self, key: &[u8]) -> Result<(), error::Error<T::Error>> {
+        self.run(request::set_cert_key(key))
     }
 
-    pub fn set_dns(
+    pub fn set_ip_config(
         &mut self,
-        valid_params: u8,
-        dns_server1: no_std_net::Ipv4Addr,
-        dns_server2: no_std_net::Ipv4Addr,
+        ip_config: &types::IpConfig,
     ) -> Result<(), error::Error<T::Error>> {
-        let send_params = (
-            valid_params,
-            param::Scalar::be(u32::from(dns_server1)),
-            param::Scalar::be(u32::from(dns_server2)),
-        );
-        let mut recv_params = (0u8,);
-
-        self.handle_cmd(
-            command::Command::SetDnsConfigCmd,
-            &send_params,
-            &mut recv_params,
-        )?;
-
-        let (status,) = recv_params;
-
-        if status == 1 {
-            Ok(())
-        } else {
-            Err(error::Error::SetDnsConfig)
+        let (config, dns) = request::ip_config(ip_config);
+        self.run(config)?;
+        if let Some(dns) = dns {
+            self.run(dns)?;
         }
+        Ok(())
     }
 
     pub fn set_hostname(&mut self, hostname: &str) -> Result<(), error::Error<T::Error>> {
-        let send_params = (param::NullTerminated::new(hostname.as_bytes()),);
-        let mut recv_params = (0u8,);
-
-        self.handle_cmd(
-            command::Command::SetHostnameCmd,
-            &send_params,
-            &mut recv_params,
-        )?;
-
-        let (status,) = recv_params;
-
-        if status == 1 {
-            Ok(())
-        } else {
-            Err(error::Error::SetHostname)
-        }
+        self.run(request::set_hostname(hostname))
     }
 
     pub fn disconnect(&mut self) -> Result<(), error::Error<T::Error>> {
-        let send_params = (0u8,);
-        let mut recv_params = (0u8,);
-
-        self.handle_cmd(
-            command::Command::DisconnectCmd,
-            &send_params,
-            &mut recv_params,
-        )?;
-
-        let (status,) = recv_params;
-
-        if status == 1 {
-            Ok(())
-        } else {
-            Err(error::Error::Disconnect)
-        }
+        self.run(request::disconnect())
     }
 
     pub fn get_current_ssid(
         &mut self,
     ) -> Result<arrayvec::ArrayVec<[u8; 32]>, error::Error<T::Error>> {
-        let send_params = (0u8,);
-        let mut recv_params = (arrayvec::ArrayVec::new(),);
-
-        self.handle_cmd(
-            command::Command::GetCurrSsidCmd,
-            &send_params,
-            &mut recv_params,
-        )?;
-
-        let (ssid,) = recv_params;
-
-        Ok(ssid)
+        self.run(request::get_current_ssid())
     }
 
     pub fn get_current_bssid(
         &mut self,
     ) -> Result<arrayvec::ArrayVec<[u8; 6]>, error::Error<T::Error>> {
-        let send_params = (0u8,);
-        let mut recv_params = (arrayvec::ArrayVec::new(),);
-
-        self.handle_cmd(
-            command::Command::GetCurrBssidCmd,
-            &send_params,
-            &mut recv_params,
-        )?;
-
-        Ok(recv_params.0)
+        self.run(request::get_current_bssid())
     }
 
     pub fn get_current_rssi(&mut self) -> Result<i32, error::Error<T::Error>> {
-        let send_params = (0u8,);
-        let mut recv_params = (param::Scalar::be(0u32),);
-
-        self.handle_cmd(
-            command::Command::GetCurrRssiCmd,
-            &send_params,
-            &mut recv_params,
-        )?;
-
-        let (rssi,) = recv_params;
-
-        Ok(rssi.into_inner() as i32)
+        self.run(request::get_current_rssi())
     }
 
     pub fn get_current_encryption_type(
         &mut self,
     ) -> Result<types::EncryptionType, error::Error<T::Error>> {
-        use core::convert::TryFrom;
-
-        let send_params = (0u8,);
-        let mut recv_params = (0u8,);
-
-        self.handle_cmd(
-            command::Command::GetCurrEnctCmd,
-            &send_params,
-            &mut recv_params,
-        )?;
-
-        let (encryption_type,) = recv_params;
-
-        let encryption_type = types::EncryptionType::try_from(encryption_type)
-            .map_err(error::Error::BadEncryptionType)?;
-
-        Ok(encryption_type)
+        self.run(request::get_current_encryption_type())
     }
 
     pub fn start_client_by_ip(
@@ -483,82 +198,74 @@ where
         socket: types::Socket,
         protocol_mode: types::ProtocolMode,
     ) -> Result<(), error::Error<T::Error>> {
-        let send_params = (
-            param::Scalar::be(u32::from(ip)),
-            param::Scalar::be(port),
-            socket.0,
-            u8::from(protocol_mode),
-        );
-        let mut recv_params = (0u8,);
-
-        self.handle_cmd(
-            command::Command::StartClientTcpCmd,
-            &send_params,
-            &mut recv_params,
-        )?;
-
-        let (status,) = recv_params;
+        self.run(request::start_client_by_ip(ip, port, socket, protocol_mode))
+    }
 
-        if status == 1 {
-            Ok(())
-        } else {
-            Err(error::Error::StartClientByIp)
-        }
+    pub fn start_client_by_hostname(
+        &mut self,
+        hostname: &str,
+        port: u16,
+        socket: types::Socket,
+        protocol_mode: types::ProtocolMode,
+    ) -> Result<(), error::Error<T::Error>> {
+        self.run(request::start_client_by_hostname(
+            hostname,
+            port,
+            socket,
+            protocol_mode,
+        ))
     }
 
-    pub fn stop_client(&mut self, socket: types::Socket) -> Result<(), error::Error<T::Error>> {
-        let send_params = (socket.0,);
-        let mut recv_params = (0u8,);
+    pub fn start_server(
+        &mut self,
+        port: u16,
+        socket: types::Socket,
+        protocol_mode: types::ProtocolMode,
+    ) -> Result<(), error::Error<T::Error>> {
+        self.run(request::start_server(port, socket, protocol_mode))
+    }
 
-        self.handle_cmd(
-            command::Command::StopClientTcpCmd,
-            &send_params,
-            &mut recv_params,
-        )?;
+    pub fn start_server_multicast(
+        &mut self,
+        group: no_std_net::Ipv4Addr,
+        port: u16,
+        socket: types::Socket,
+    ) -> Result<(), error::Error<T::Error>> {
+        self.run(request::start_server_multicast(group, port, socket))
+    }
 
-        let (status,) = recv_params;
+    pub fn get_server_state(
+        &mut self,
+        socket: types::Socket,
+    ) -> Result<types::TcpState, error::Error<T::Error>> {
+        self.run(request::get_server_state(socket))
+    }
 
-        if status == 1 {
-            Ok(())
-        } else {
-            Err(error::Error::StopClient)
+    pub fn avail_server(
+        &mut self,
+        socket: types::Socket,
+    ) -> Result<Option<types::Socket>, error::Error<T::Error>> {
+        // For listening sockets, the firmware reports the socket of an accepted client instead of
+        // the number of available bytes, or 255 if there is no pending connection.
+        match self.avail_data(socket)? {
+            255 => Ok(None),
+            client => Ok(Some(types::Socket(client as u8))),
         }
     }
 
+    pub fn stop_client(&mut self, socket: types::Socket) -> Result<(), error::Error<T::Error>> {
+        self.run(request::stop_client(socket))
+    }
+
     pub fn get_client_state(
         &mut self,
         socket: types::Socket,
     ) -> Result<types::TcpState, error::Error<T::Error>> {
-        use core::convert::TryFrom;
-
-        let send_params = (socket.0,);
-        let mut recv_params = (0u8,);
-
-        self.handle_cmd(
-            command::Command::GetClientStateTcpCmd,
-            &send_params,
-            &mut recv_params,
-        )?;
-
-        let (state,) = recv_params;
-        let state = types::TcpState::try_from(state).map_err(error::Error::BadTcpState)?;
-
-        Ok(state)
+        self.run(request::get_client_state(socket))
     }
 
     pub fn avail_data(&mut self, socket: types::Socket) -> Result<u16, error::Error<T::Error>> {
-        let send_params = (socket.0,);
-        let mut recv_params = (param::Scalar::le(0u16),);
-
-        self.handle_cmd(
-            command::Command::AvailDataTcpCmd,
-            &send_params,
-            &mut recv_params,
-        )?;
-
-        let (data,) = recv_params;
-
-        Ok(data.into_inner())
+        self.run(request::avail_data(socket))
     }
 
     pub fn get_data_buf(
@@ -566,20 +273,7 @@ where
         socket: types::Socket,
         buf: &mut [u8],
     ) -> Result<usize, error::Error<T::Error>> {
-        use core::convert::TryFrom;
-        let send_params = (
-            socket.0,
-            param::Scalar::le(u16::try_from(buf.len()).unwrap()),
-        );
-        let mut recv_params = (buf,);
-
-        self.handle_long_send_long_recv_cmd(
-            command::Command::GetDatabufTcpCmd,
-            &send_params,
-            &mut recv_params,
-        )?;
-
-        Ok(recv_params.0.len())
+        self.run(request::get_data_buf(socket, buf))
     }
 
     pub fn send_data(
@@ -587,48 +281,27 @@ where
         socket: types::Socket,
         data: &[u8],
     ) -> Result<usize, error::Error<T::Error>> {
-        let send_params = (socket.0, data);
-        let mut recv_params = (param::Scalar::le(0u16),);
-
-        self.handle_long_send_cmd(
-            command::Command::SendDataTcpCmd,
-            &send_params,
-            &mut recv_params,
-        )?;
-
-        let (len,) = recv_params;
-
-        Ok(len.into_inner() as usize)
+        self.run(request::send_data(socket, data))
     }
 
     pub fn check_data_sent(&mut self, socket: types::Socket) -> Result<(), error::Error<T::Error>> {
-        let send_params = (socket.0,);
-        let mut recv_params = (0u8,);
-
-        self.handle_cmd(
-            command::Command::DataSentTcpCmd,
-            &send_params,
-            &mut recv_params,
-        )?;
+        self.run(request::check_data_sent(socket))
+    }
 
-        let (status,) = recv_params;
+    pub fn insert_data_buf(
+        &mut self,
+        socket: types::Socket,
+        data: &[u8],
+    ) -> Result<(), error::Error<T::Error>> {
+        self.run(request::insert_data_buf(socket, data))
+    }
 
-        if status == 1 {
-            Ok(())
-        } else {
-            Err(error::Error::CheckDataSent)
-        }
+    pub fn send_udp_data(&mut self, socket: types::Socket) -> Result<(), error::Error<T::Error>> {
+        self.run(request::send_udp_data(socket))
     }
 
     pub fn get_socket(&mut self) -> Result<types::Socket, error::Error<T::Error>> {
-        let mut recv_params = (0u8,);
-
-        self.handle_cmd(command::Command::GetSocketCmd, &(), &mut recv_params)?;
-
-        let (socket,) = recv_params;
-        let socket = types::Socket(socket);
-
-        Ok(socket)
+        self.run(request::get_socket())
     }
 
     pub fn pin_mode(
@@ -636,100 +309,89 @@ where
         pin: u8,
         mode: types::PinMode,
     ) -> Result<(), error::Error<T::Error>> {
-        let send_params = (pin, u8::from(mode));
-        let mut recv_params = (0u8,);
-
-        self.handle_cmd(command::Command::SetPinMode, &send_params, &mut recv_params)?;
-
-        let (status,) = recv_params;
-
-        if status == 1 {
-            Ok(())
-        } else {
-            Err(error::Error::PinMode)
-        }
+        self.run(request::pin_mode(pin, mode))
     }
 
     pub fn digital_write(&mut self, pin: u8, value: u8) -> Result<(), error::Error<T::Error>> {
-        let send_params = (pin, value);
-        let mut recv_params = (0u8,);
-
-        self.handle_cmd(
-            command::Command::SetDigitalWrite,
-            &send_params,
-            &mut recv_params,
-        )?;
-
-        let (status,) = recv_params;
-
-        if status == 1 {
-            Ok(())
-        } else {
-            Err(error::Error::DigitalWrite)
-        }
+        self.run(request::digital_write(pin, value))
     }
 
     pub fn analog_write(&mut self, pin: u8, value: u8) -> Result<(), error::Error<T::Error>> {
-        let send_params = (pin, value);
-        let mut recv_params = (0u8,);
-
-        self.handle_cmd(
-            command::Command::SetAnalogWrite,
-            &send_params,
-            &mut recv_params,
-        )?;
-
-        let (status,) = recv_params;
-
-        if status == 1 {
-            Ok(())
-        } else {
-            Err(error::Error::AnalogWrite)
-        }
+        self.run(request::analog_write(pin, value))
     }
 
-    fn handle_cmd<SP, RP>(
+    fn run<SP, RP, O>(
         &mut self,
-        command: command::Command,
-        send_params: &SP,
-        recv_params: &mut RP,
-    ) -> Result<(), error::Error<T::Error>>
+        mut request: request::Request<SP, RP, O, T::Error>,
+    ) -> Result<O, error::Error<T::Error>>
     where
         SP: params::SendParams + fmt::Debug,
         RP: params::RecvParams + fmt::Debug,
     {
-        self.transport
-            .handle_cmd(command, send_params, recv_params, false, false)
-            .map_err(error::Error::Transport)
+        self.handle_cmd_recovering(
+            request.command,
+            &request.send_params,
+            &mut request.recv_params,
+            request.long_send,
+            request.long_recv,
+        )?;
+        request.finish()
     }
 
-    fn handle_long_send_cmd<SP, RP>(
-        &mut self,
-        command: command::Command,
-        send_params: &SP,
-        recv_params: &mut RP,
-    ) -> Result<(), error::Error<T::Error>>
-    where
-        SP: params::SendParams + fmt::Debug,
-        RP: params::RecvParams + fmt::Debug,
-    {
-        self.transport
-            .handle_cmd(command, send_params, recv_params, true, false)
-            .map_err(error::Error::Transport)
+    fn transport_error(error: T::Error) -> error::Error<T::Error> {
+        match T::protocol_error(&error) {
+            Some(error) => error::Error::Protocol(error),
+            None => error::Error::Transport(error),
+        }
     }
 
-    fn handle_long_send_long_recv_cmd<SP, RP>(
+    fn handle_cmd_recovering<SP, RP>(
         &mut self,
         command: command::Command,
         send_params: &SP,
         recv_params: &mut RP,
+        long_send: bool,
+        long_recv: bool,
     ) -> Result<(), error::Error<T::Error>>
     where
         SP: params::SendParams + fmt::Debug,
         RP: params::RecvParams + fmt::Debug,
     {
-        self.transport
-            .handle_cmd(command, send_params, recv_params, true, true)
-            .map_err(error::Error::Transport)
+        send_params
+            .check(long_send)
+            .map_err(error::Error::Protocol)?;
+
+        let mut retries = 0;
+        loop {
+            let error = match self.transport.handle_cmd(
+                command,
+                send_params,
+                recv_params,
+                long_send,
+                long_recv,
+            ) {
+                Ok(()) => {
+                    self.recovery.succeeded();
+                    return Ok(());
+                }
+                Err(error) => error,
+            };
+
+            let desync = T::is_desync(&error);
+            if desync {
+                log::debug!("resync after {:?} failed", command);
+                self.transport.resync().map_err(error::Error::Transport)?;
+            }
+
+            match self.recovery.failed(command, desync, retries) {
+                recovery::Action::Retry => retries += 1,
+                recovery::Action::Reset => {
+                    self.transport.reset().map_err(error::Error::Transport)?;
+                    self.reset_pending = true;
+                    return Err(Self::transport_error(error));
+                }
+                recovery::Action::Fail => return Err(Self::transport_error(error)),
+            }
+        }
     }
 }
@@ -1,33 +1,86 @@
 #![no_std]
+#![allow(clippy::type_complexity)]
+
+#[cfg(feature = "std")]
+extern crate std;
 
 use core::marker;
 use core::time;
 
-mod command;
+#[cfg(feature = "async")]
+pub mod asynch;
+pub mod command;
 mod encoding;
 mod error;
+mod frame;
 mod full_duplex;
 mod handler;
+#[cfg(feature = "embedded-nal")]
+pub mod nal;
 mod param;
 mod params;
+mod recovery;
+mod request;
+mod sockets;
+mod timer;
 pub mod transport;
 pub mod types;
 
-pub use error::Error;
+pub use error::{Error, ProtocolError};
+pub use sockets::SocketLeases;
 
 const BUFFER_CAPACITY: usize = 4096;
+// Sizes of the certificate and key buffers in the NINA firmware
+const CLIENT_CERT_CAPACITY: usize = 1300;
+const CERT_KEY_CAPACITY: usize = 1700;
+const DEFAULT_POLL_INTERVAL: time::Duration = time::Duration::from_millis(100);
+
+fn is_valid_hostname(hostname: &str) -> bool {
+    const MAX_HOSTNAME_LEN: usize = 32;
+
+    !hostname.is_empty()
+        && hostname.len() <= MAX_HOSTNAME_LEN
+        && !hostname.starts_with('-')
+        && !hostname.ends_with('-')
+        && hostname
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-')
+}
 
 #[derive(Debug)]
-pub struct Wifi<T> {
+pub struct Wifi<T>
+where
+    T: transport::Transport,
+{
     handler: handler::Handler<T>,
     led_init: bool,
+    reinit: Option<fn(&mut Wifi<T>) -> Result<(), error::Error<T::Error>>>,
+    clock: Option<fn() -> time::Duration>,
+    poll_interval: time::Duration,
+    sockets: sockets::SocketTable,
 }
 
 #[derive(Debug)]
-pub struct Client<T> {
+pub struct Client<T, const N: usize = { BUFFER_CAPACITY }> {
     socket: types::Socket,
-    buffer_offset: usize,
-    buffer: arrayvec::ArrayVec<[u8; BUFFER_CAPACITY]>,
+    buffer: [u8; N],
+    buffer_start: usize,
+    buffer_end: usize,
+    _lease: sockets::Lease,
+    phantom: marker::PhantomData<T>,
+}
+
+#[derive(Debug)]
+pub struct Server<T> {
+    socket: types::Socket,
+    _lease: sockets::Lease,
+    phantom: marker::PhantomData<T>,
+}
+
+#[derive(Debug)]
+pub struct UdpSocket<T> {
+    socket: types::Socket,
+    _lease: sockets::Lease,
     phantom: marker::PhantomData<T>,
 }
 
@@ -38,26 +91,67 @@ where
     pub fn new(transport: T) -> Self {
         let handler = handler::Handler::new(transport);
         let led_init = false;
-        Self { handler, led_init }
+        let reinit = None;
+        let clock = None;
+        let poll_interval = DEFAULT_POLL_INTERVAL;
+        let sockets = sockets::SocketTable::default();
+        Self {
+            handler,
+            led_init,
+            reinit,
+            clock,
+            poll_interval,
+            sockets,
+        }
+    }
+
+    pub fn transport(&self) -> &T {
+        self.handler.transport()
+    }
+
+    pub fn transport_mut(&mut self) -> &mut T {
+        self.handler.transport_mut()
+    }
+
+    pub fn set_recovery(&mut self, recovery: types::RecoveryConfig) {
+        self.handler.set_recovery(recovery);
+    }
+
+    // Runs before the next command after the recovery has reset the module
+    pub fn set_reinit_hook(
+        &mut self,
+        reinit: fn(&mut Wifi<T>) -> Result<(), error::Error<T::Error>>,
+    ) {
+        self.reinit = Some(reinit);
+    }
+
+    // Without a clock, timeouts only count the time spent in delays
+    pub fn set_clock(&mut self, now: fn() -> time::Duration) {
+        self.clock = Some(now);
+        self.transport_mut().set_clock(now);
+    }
+
+    pub fn set_poll_interval(&mut self, poll_interval: time::Duration) {
+        self.poll_interval = poll_interval;
     }
 
     pub fn get_firmware_version(
         &mut self,
     ) -> Result<arrayvec::ArrayVec<[u8; 16]>, error::Error<T::Error>> {
-        self.handler.get_firmware_version()
+        self.handler()?.get_firmware_version()
     }
 
     pub fn set_led(&mut self, r: u8, g: u8, b: u8) -> Result<(), error::Error<T::Error>> {
         if !self.led_init {
-            self.handler.pin_mode(25, types::PinMode::Output)?;
-            self.handler.pin_mode(26, types::PinMode::Output)?;
-            self.handler.pin_mode(27, types::PinMode::Output)?;
+            for &pin in &request::LED_PINS {
+                self.handler()?.pin_mode(pin, types::PinMode::Output)?;
+            }
             self.led_init = true;
         }
 
-        self.handler.analog_write(25, r)?;
-        self.handler.analog_write(26, g)?;
-        self.handler.analog_write(27, b)?;
+        for (&pin, &value) in request::LED_PINS.iter().zip(&[r, g, b]) {
+            self.handler()?.analog_write(pin, value)?;
+        }
 
         Ok(())
     }
@@ -67,44 +161,55 @@ where
         config: types::Config,
         connect_timeout: Option<time::Duration>,
     ) -> Result<(), error::Error<T::Error>> {
-        match config {
-            types::Config::Station(station_config) => match station_config.network {
-                types::NetworkConfig::Open { ssid } => self.handler.set_network(ssid)?,
-                types::NetworkConfig::Password { ssid, password } => {
-                    self.handler.set_passphrase(ssid, password)?
-                }
-            },
-            types::Config::AccessPoint(_) => unimplemented!(),
+        if let types::Config::Station(station_config) = &config {
+            if let Some(ip_config) = station_config.ip_config {
+                self.set_ip_config(&ip_config)?;
+            }
         }
 
+        self.handler()?.join_network(&config)?;
+
         if let Some(connect_timeout) = connect_timeout {
-            self.await_connection_state(types::ConnectionState::Connected, connect_timeout)?;
+            self.await_connection_state_matching(|state| config.is_up(state), connect_timeout)?;
         }
 
         Ok(())
     }
 
+    pub fn disconnect(&mut self) -> Result<(), error::Error<T::Error>> {
+        self.handler()?.disconnect()
+    }
+
     pub fn await_connection_state(
         &mut self,
         connection_state: types::ConnectionState,
         timeout: time::Duration,
     ) -> Result<(), error::Error<T::Error>> {
-        const POLL_INTEVAL: time::Duration = time::Duration::from_millis(100);
+        self.await_connection_state_matching(|state| state == connection_state, timeout)
+    }
 
-        let mut total_time = time::Duration::new(0, 0);
+    fn await_connection_state_matching(
+        &mut self,
+        mut predicate: impl FnMut(types::ConnectionState) -> bool,
+        timeout: time::Duration,
+    ) -> Result<(), error::Error<T::Error>> {
+        let mut timer = timer::Timer::start(self.clock);
 
         let mut actual_connection_state;
         loop {
-            actual_connection_state = self.handler.get_connection_state()?;
-            if connection_state == actual_connection_state {
+            actual_connection_state = self.handler()?.get_connection_state()?;
+            if predicate(actual_connection_state) {
                 return Ok(());
             }
+            if actual_connection_state == types::ConnectionState::ApFailed {
+                break;
+            }
 
-            self.handler.delay(POLL_INTEVAL)?;
-            // TODO: don't assume the actual SPI transfer takes 0 time :)
-            total_time += POLL_INTEVAL;
+            let poll_interval = self.poll_interval;
+            self.handler()?.delay(poll_interval)?;
+            timer.add_delay(poll_interval);
 
-            if total_time > timeout {
+            if timer.elapsed() > timeout {
                 break;
             }
         }
@@ -112,24 +217,49 @@ where
         Err(error::Error::ConnectionFailure(actual_connection_state))
     }
 
+    // Must be done before connecting
+    pub fn set_ip_config(
+        &mut self,
+        ip_config: &types::IpConfig,
+    ) -> Result<(), error::Error<T::Error>> {
+        self.handler()?.set_ip_config(ip_config)
+    }
+
+    pub fn mac_address(&mut self) -> Result<types::MacAddress, error::Error<T::Error>> {
+        self.handler()?.get_mac_address()
+    }
+
+    // The hostname must be a DNS label of at most 32 characters
+    pub fn set_hostname(&mut self, hostname: &str) -> Result<(), error::Error<T::Error>> {
+        if !is_valid_hostname(hostname) {
+            return Err(error::Error::InvalidHostname);
+        }
+
+        self.handler()?.set_hostname(hostname)
+    }
+
+    pub fn network_data(&mut self) -> Result<types::NetworkData, error::Error<T::Error>> {
+        self.handler()?.get_network_data()
+    }
+
     pub fn scan_networks<'a>(
         &'a mut self,
     ) -> Result<
         impl Iterator<Item = Result<types::ScannedNetwork, error::Error<T::Error>>> + 'a,
         error::Error<T::Error>,
     > {
-        self.handler.start_scan_networks()?;
+        self.handler()?.start_scan_networks()?;
         Ok(self
-            .handler
+            .handler()?
             .get_scanned_networks()?
             .into_iter()
             .enumerate()
             .map(move |(i, ssid)| {
                 let i = i as u8;
-                let rssi = self.handler.get_scanned_network_rssi(i)?;
-                let encryption_type = self.handler.get_scanned_network_encryption_type(i)?;
-                let bssid = self.handler.get_scanned_network_bssid(i)?;
-                let channel = self.handler.get_scanned_network_channel(i)?;
+                let rssi = self.handler()?.get_scanned_network_rssi(i)?;
+                let encryption_type = self.handler()?.get_scanned_network_encryption_type(i)?;
+                let bssid = self.handler()?.get_scanned_network_bssid(i)?;
+                let channel = self.handler()?.get_scanned_network_channel(i)?;
 
                 Ok(types::ScannedNetwork {
                     ssid,
@@ -142,47 +272,222 @@ where
     }
 
     pub fn ssid(&mut self) -> Result<arrayvec::ArrayVec<[u8; 32]>, error::Error<T::Error>> {
-        self.handler.get_current_ssid()
+        self.handler()?.get_current_ssid()
     }
 
     pub fn bssid(&mut self) -> Result<arrayvec::ArrayVec<[u8; 6]>, error::Error<T::Error>> {
-        self.handler.get_current_bssid()
+        self.handler()?.get_current_bssid()
     }
 
     pub fn rssi(&mut self) -> Result<i32, error::Error<T::Error>> {
-        self.handler.get_current_rssi()
+        self.handler()?.get_current_rssi()
     }
 
     pub fn encryption_type(&mut self) -> Result<types::EncryptionType, error::Error<T::Error>> {
-        self.handler.get_current_encryption_type()
+        self.handler()?.get_current_encryption_type()
     }
 
     pub fn resolve(
         &mut self,
         hostname: &str,
     ) -> Result<no_std_net::Ipv4Addr, error::Error<T::Error>> {
-        self.handler.request_host_by_name(hostname)?;
-        self.handler.get_host_by_name()
+        self.handler()?.request_host_by_name(hostname)?;
+        self.handler()?.get_host_by_name()
+    }
+
+    // Used by all later TLS connections
+    pub fn set_client_certificate(
+        &mut self,
+        cert: &[u8],
+        key: &[u8],
+    ) -> Result<(), error::Error<T::Error>> {
+        if cert.len() > CLIENT_CERT_CAPACITY || key.len() > CERT_KEY_CAPACITY {
+            return Err(error::Error::DataTooLong);
+        }
+
+        self.handler()?.set_client_cert(cert)?;
+        self.handler()?.set_cert_key(key)
+    }
+
+    // Must be set before any socket is handed out
+    pub fn set_socket_leases(&mut self, leases: &'static SocketLeases) {
+        self.sockets.set_leases(leases);
+    }
+
+    // Stops the sockets whose handles were dropped without closing them
+    pub fn reclaim_sockets(&mut self) -> Result<usize, error::Error<T::Error>> {
+        if !self.sockets.has_leases() {
+            return Err(error::Error::Unsupported);
+        }
+
+        let mut reclaimed = 0;
+        for i in 0..sockets::MAX_SOCKETS {
+            let socket = types::Socket(i as u8);
+            if self.sockets.is_orphaned(socket) {
+                self.handler()?.stop_client(socket)?;
+                self.sockets.release(socket);
+                reclaimed += 1;
+            }
+        }
+        Ok(reclaimed)
+    }
+
+    pub fn sockets(&self) -> impl Iterator<Item = types::SocketInfo> + '_ {
+        self.sockets.iter()
+    }
+
+    pub fn available_sockets(&self) -> usize {
+        self.sockets.available()
+    }
+
+    fn allocate_socket(
+        &mut self,
+        kind: types::SocketKind,
+    ) -> Result<(types::Socket, sockets::Lease), error::Error<T::Error>> {
+        let socket = match self.handler()?.get_socket()? {
+            // The firmware answers 255 when it has no free socket left
+            types::Socket(255) => return Err(error::Error::NoFreeSocket),
+            socket => socket,
+        };
+        // The firmware hands out the lowest socket that hasn't been started, which may be allocated
+        // to a handle that isn't connected yet, so that socket is refused as in use
+        let lease = self.track_socket(socket, kind)?;
+        Ok((socket, lease))
+    }
+
+    fn track_socket(
+        &mut self,
+        socket: types::Socket,
+        kind: types::SocketKind,
+    ) -> Result<sockets::Lease, error::Error<T::Error>> {
+        self.sockets.allocate(socket, kind).map_err(|e| match e {
+            sockets::AllocateError::Exhausted => error::Error::NoFreeSocket,
+            sockets::AllocateError::InUse(socket) => error::Error::SocketInUse(socket),
+        })
+    }
+
+    fn listen_socket(
+        &mut self,
+        socket: types::Socket,
+        port: u16,
+        protocol_mode: types::ProtocolMode,
+    ) -> Result<(), error::Error<T::Error>> {
+        self.handler()?.start_server(port, socket, protocol_mode)?;
+        self.sockets
+            .set_started(socket, protocol_mode, types::SocketState::Listening);
+        Ok(())
+    }
+
+    // Takes a client that the firmware accepted on the listening `server` socket
+    fn accept_socket(
+        &mut self,
+        server: types::Socket,
+    ) -> nb::Result<(types::Socket, sockets::Lease), error::Error<T::Error>> {
+        let socket = self
+            .handler()?
+            .avail_server(server)?
+            .ok_or(nb::Error::WouldBlock)?;
+
+        // The firmware keeps reporting an accepted client for as long as it has unread data
+        match self.sockets.get(socket) {
+            Some(info)
+                if info.kind == types::SocketKind::Client
+                    && info.state == types::SocketState::Connected =>
+            {
+                return Err(nb::Error::WouldBlock);
+            }
+            // The firmware put the client on a socket allocated to a handle that isn't started
+            // yet, which can't be told apart from it, so the client is turned away
+            Some(_) => {
+                self.handler()?.stop_client(socket)?;
+                return Err(nb::Error::Other(error::Error::SocketInUse(socket)));
+            }
+            None => {}
+        }
+
+        let lease = self.track_socket(socket, types::SocketKind::Client)?;
+        self.sockets.set_started(
+            socket,
+            types::ProtocolMode::Tcp,
+            types::SocketState::Connected,
+        );
+        Ok((socket, lease))
+    }
+
+    fn close_socket(&mut self, socket: types::Socket) -> Result<(), error::Error<T::Error>> {
+        self.handler()?.stop_client(socket)?;
+        self.sockets.release(socket);
+        Ok(())
+    }
+
+    fn max_data_len(&self) -> usize {
+        self.transport().max_data_len()
+    }
+
+    fn handler(&mut self) -> Result<&mut handler::Handler<T>, error::Error<T::Error>> {
+        if self.handler.take_reset_pending() {
+            // The module lost its LED pin configuration and sockets too
+            self.led_init = false;
+            self.sockets.clear();
+            if let Some(reinit) = self.reinit {
+                reinit(self)?;
+            }
+        }
+        Ok(&mut self.handler)
     }
 
     pub fn new_client(&mut self) -> Result<Client<T>, error::Error<T::Error>> {
-        let socket = self.handler.get_socket()?;
-        let buffer_offset = 0;
-        let buffer = arrayvec::ArrayVec::new();
+        self.new_client_with_buffer()
+    }
+
+    // Only clients with a buffer can peek
+    pub fn new_client_with_buffer<const N: usize>(
+        &mut self,
+    ) -> Result<Client<T, N>, error::Error<T::Error>> {
+        let (socket, lease) = self.allocate_socket(types::SocketKind::Client)?;
+        Ok(Client::new(socket, lease))
+    }
+
+    pub fn new_server(&mut self) -> Result<Server<T>, error::Error<T::Error>> {
+        let (socket, lease) = self.allocate_socket(types::SocketKind::Server)?;
         let phantom = marker::PhantomData;
-        Ok(Client {
+        Ok(Server {
             socket,
-            buffer_offset,
-            buffer,
+            _lease: lease,
+            phantom,
+        })
+    }
+
+    pub fn new_udp_socket(&mut self) -> Result<UdpSocket<T>, error::Error<T::Error>> {
+        let (socket, lease) = self.allocate_socket(types::SocketKind::Udp)?;
+        let phantom = marker::PhantomData;
+        Ok(UdpSocket {
+            socket,
+            _lease: lease,
             phantom,
         })
     }
 }
 
-impl<T> Client<T>
+impl<T, const N: usize> Client<T, N>
 where
     T: transport::Transport,
 {
+    fn new(socket: types::Socket, lease: sockets::Lease) -> Self {
+        let buffer = [0; N];
+        let buffer_start = 0;
+        let buffer_end = 0;
+        let phantom = marker::PhantomData;
+        Client {
+            socket,
+            buffer,
+            buffer_start,
+            buffer_end,
+            _lease: lease,
+            phantom,
+        }
+    }
+
     pub fn connect_ipv4(
         &mut self,
         wifi: &mut Wifi<T>,
@@ -190,8 +495,34 @@ where
         port: u16,
         protocol_mode: types::ProtocolMode,
     ) -> Result<(), error::Error<T::Error>> {
-        wifi.handler
-            .start_client_by_ip(ip, port, self.socket, protocol_mode)
+        wifi.handler()?
+            .start_client_by_ip(ip, port, self.socket, protocol_mode)?;
+        wifi.sockets
+            .set_started(self.socket, protocol_mode, types::SocketState::Connected);
+        Ok(())
+    }
+
+    pub fn connect_hostname(
+        &mut self,
+        wifi: &mut Wifi<T>,
+        hostname: &str,
+        port: u16,
+        protocol_mode: types::ProtocolMode,
+    ) -> Result<(), error::Error<T::Error>> {
+        wifi.handler()?
+            .start_client_by_hostname(hostname, port, self.socket, protocol_mode)?;
+        wifi.sockets
+            .set_started(self.socket, protocol_mode, types::SocketState::Connected);
+        Ok(())
+    }
+
+    pub fn connect_tls(
+        &mut self,
+        wifi: &mut Wifi<T>,
+        hostname: &str,
+        port: u16,
+    ) -> Result<(), error::Error<T::Error>> {
+        self.connect_hostname(wifi, hostname, port, types::ProtocolMode::Tls)
     }
 
     pub fn send(
@@ -199,9 +530,9 @@ where
         wifi: &mut Wifi<T>,
         data: &[u8],
     ) -> Result<usize, error::Error<T::Error>> {
-        let len = data.len().min(u16::max_value() as usize);
-        let sent = wifi.handler.send_data(self.socket, &data[..len])?;
-        wifi.handler.check_data_sent(self.socket)?;
+        let len = data.len().min(wifi.max_data_len());
+        let sent = wifi.handler()?.send_data(self.socket, &data[..len])?;
+        wifi.handler()?.check_data_sent(self.socket)?;
         Ok(sent)
     }
 
@@ -218,7 +549,21 @@ where
     }
 
     pub fn state(&mut self, wifi: &mut Wifi<T>) -> Result<types::TcpState, error::Error<T::Error>> {
-        wifi.handler.get_client_state(self.socket)
+        wifi.handler()?.get_client_state(self.socket)
+    }
+
+    pub fn socket(&self) -> types::Socket {
+        self.socket
+    }
+
+    pub fn close(self, wifi: &mut Wifi<T>) -> Result<(), error::Error<T::Error>> {
+        wifi.close_socket(self.socket)
+    }
+
+    pub fn available(&mut self, wifi: &mut Wifi<T>) -> Result<usize, error::Error<T::Error>> {
+        let buffered = self.buffer_end - self.buffer_start;
+        let pending = wifi.handler()?.avail_data(self.socket)?;
+        Ok(buffered + usize::from(pending))
     }
 
     pub fn recv(
@@ -226,25 +571,95 @@ where
         wifi: &mut Wifi<T>,
         data: &mut [u8],
     ) -> Result<usize, error::Error<T::Error>> {
-        if self.buffer_offset >= self.buffer.len() {
-            self.buffer.clear();
-            self.buffer
-                .try_extend_from_slice(&[0; BUFFER_CAPACITY])
-                .unwrap();
-            let recv_len = wifi
-                .handler
-                .get_data_buf(self.socket, self.buffer.as_mut())?;
-            self.buffer.truncate(recv_len);
-            self.buffer_offset = 0;
-            log::debug!("fetched new buffer of len {}", self.buffer.len());
-        }
-
-        let len = data.len().min(self.buffer.len() - self.buffer_offset);
-        data[..len].copy_from_slice(&self.buffer[self.buffer_offset..self.buffer_offset + len]);
-        self.buffer_offset += len;
+        if self.buffer_start >= self.buffer_end {
+            // A slice at least as large as the buffer is filled directly, skipping the copy;
+            // without a buffer, this is always the case
+            if data.len() >= N {
+                let len = data.len().min(wifi.max_data_len());
+                return wifi.handler()?.get_data_buf(self.socket, &mut data[..len]);
+            }
+            self.fill_buffer(wifi)?;
+        }
+
+        let len = self.peek_buffer(data);
+        self.buffer_start += len;
         Ok(len)
     }
 
+    // Ok(0) means the connection was closed, WouldBlock that no data has arrived yet
+    pub fn try_recv(
+        &mut self,
+        wifi: &mut Wifi<T>,
+        data: &mut [u8],
+    ) -> nb::Result<usize, error::Error<T::Error>> {
+        if !self.poll_data(wifi)? {
+            return Ok(0);
+        }
+        if data.is_empty() {
+            return Err(nb::Error::WouldBlock);
+        }
+
+        match self.recv(wifi, data)? {
+            0 => Err(nb::Error::WouldBlock),
+            len => Ok(len),
+        }
+    }
+
+    // Unsupported for clients without a buffer
+    pub fn peek(
+        &mut self,
+        wifi: &mut Wifi<T>,
+        data: &mut [u8],
+    ) -> nb::Result<usize, error::Error<T::Error>> {
+        if N == 0 {
+            return Err(nb::Error::Other(error::Error::Unsupported));
+        }
+
+        if !self.poll_data(wifi)? {
+            return Ok(0);
+        }
+        if data.is_empty() {
+            return Err(nb::Error::WouldBlock);
+        }
+
+        if self.buffer_start >= self.buffer_end {
+            self.fill_buffer(wifi)?;
+        }
+        match self.peek_buffer(data) {
+            0 => Err(nb::Error::WouldBlock),
+            len => Ok(len),
+        }
+    }
+
+    pub fn recv_timeout(
+        &mut self,
+        wifi: &mut Wifi<T>,
+        data: &mut [u8],
+        timeout: time::Duration,
+    ) -> Result<usize, error::Error<T::Error>> {
+        if data.is_empty() {
+            return Ok(0);
+        }
+
+        let mut timer = timer::Timer::start(wifi.clock);
+        self.recv_until(wifi, data, &mut timer, timeout)
+    }
+
+    // The data received so far is lost on a timeout
+    pub fn recv_exact_timeout(
+        &mut self,
+        wifi: &mut Wifi<T>,
+        mut data: &mut [u8],
+        timeout: time::Duration,
+    ) -> Result<(), error::Error<T::Error>> {
+        let mut timer = timer::Timer::start(wifi.clock);
+        while !data.is_empty() {
+            let len = self.recv_until(wifi, data, &mut timer, timeout)?;
+            data = &mut data[len..];
+        }
+        Ok(())
+    }
+
     pub fn recv_exact(
         &mut self,
         wifi: &mut Wifi<T>,
@@ -256,4 +671,522 @@ where
         }
         Ok(())
     }
+
+    fn fill_buffer(&mut self, wifi: &mut Wifi<T>) -> Result<(), error::Error<T::Error>> {
+        let len = N.min(wifi.max_data_len());
+        self.buffer_end = wifi
+            .handler()?
+            .get_data_buf(self.socket, &mut self.buffer[..len])?;
+        self.buffer_start = 0;
+        log::debug!("fetched new buffer of len {}", self.buffer_end);
+        Ok(())
+    }
+
+    fn recv_until(
+        &mut self,
+        wifi: &mut Wifi<T>,
+        data: &mut [u8],
+        timer: &mut timer::Timer,
+        timeout: time::Duration,
+    ) -> Result<usize, error::Error<T::Error>> {
+        loop {
+            match self.try_recv(wifi, data) {
+                Ok(0) => return Err(error::Error::ConnectionClosed),
+                Ok(len) => return Ok(len),
+                Err(nb::Error::Other(e)) => return Err(e),
+                Err(nb::Error::WouldBlock) => {}
+            }
+
+            if timer.elapsed() >= timeout {
+                return Err(error::Error::Timeout);
+            }
+
+            let poll_interval = wifi.poll_interval;
+            wifi.handler()?.delay(poll_interval)?;
+            timer.add_delay(poll_interval);
+        }
+    }
+
+    // Checks whether there is data to receive, returning `Ok(false)` once the peer has closed the
+    // connection and everything it sent has been received
+    fn poll_data(&mut self, wifi: &mut Wifi<T>) -> nb::Result<bool, error::Error<T::Error>> {
+        if self.buffer_start < self.buffer_end {
+            return Ok(true);
+        }
+
+        if wifi.handler()?.avail_data(self.socket)? == 0 {
+            if !self.state(wifi)?.is_peer_closed() {
+                return Err(nb::Error::WouldBlock);
+            }
+            // The peer may have sent more data right before closing
+            if wifi.handler()?.avail_data(self.socket)? == 0 {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn peek_buffer(&self, data: &mut [u8]) -> usize {
+        let len = data.len().min(self.buffer_end - self.buffer_start);
+        data[..len].copy_from_slice(&self.buffer[self.buffer_start..self.buffer_start + len]);
+        len
+    }
+}
+
+impl<T> Server<T>
+where
+    T: transport::Transport,
+{
+    pub fn listen(
+        &mut self,
+        wifi: &mut Wifi<T>,
+        port: u16,
+        protocol_mode: types::ProtocolMode,
+    ) -> Result<(), error::Error<T::Error>> {
+        wifi.listen_socket(self.socket, port, protocol_mode)
+    }
+
+    pub fn state(&mut self, wifi: &mut Wifi<T>) -> Result<types::TcpState, error::Error<T::Error>> {
+        wifi.handler()?.get_server_state(self.socket)
+    }
+
+    pub fn accept(&mut self, wifi: &mut Wifi<T>) -> nb::Result<Client<T>, error::Error<T::Error>> {
+        self.accept_with_buffer(wifi)
+    }
+
+    pub fn accept_with_buffer<const N: usize>(
+        &mut self,
+        wifi: &mut Wifi<T>,
+    ) -> nb::Result<Client<T, N>, error::Error<T::Error>> {
+        let (socket, lease) = wifi.accept_socket(self.socket)?;
+        Ok(Client::new(socket, lease))
+    }
+
+    pub fn socket(&self) -> types::Socket {
+        self.socket
+    }
+
+    pub fn close(self, wifi: &mut Wifi<T>) -> Result<(), error::Error<T::Error>> {
+        wifi.close_socket(self.socket)
+    }
+}
+
+impl<T> UdpSocket<T>
+where
+    T: transport::Transport,
+{
+    pub fn bind(&mut self, wifi: &mut Wifi<T>, port: u16) -> Result<(), error::Error<T::Error>> {
+        wifi.handler()?
+            .start_server(port, self.socket, types::ProtocolMode::Udp)?;
+        wifi.sockets.set_started(
+            self.socket,
+            types::ProtocolMode::Udp,
+            types::SocketState::Bound,
+        );
+        Ok(())
+    }
+
+    pub fn join_multicast(
+        &mut self,
+        wifi: &mut Wifi<T>,
+        group: no_std_net::Ipv4Addr,
+        port: u16,
+    ) -> Result<(), error::Error<T::Error>> {
+        if !group.is_multicast() {
+            return Err(error::Error::NotMulticast(group));
+        }
+
+        wifi.handler()?
+            .start_server_multicast(group, port, self.socket)?;
+        wifi.sockets.set_started(
+            self.socket,
+            types::ProtocolMode::UdpMulticast,
+            types::SocketState::Bound,
+        );
+        Ok(())
+    }
+
+    pub fn send_to(
+        &mut self,
+        wifi: &mut Wifi<T>,
+        data: &[u8],
+        ip: no_std_net::Ipv4Addr,
+        port: u16,
+    ) -> Result<(), error::Error<T::Error>> {
+        if data.len() > wifi.max_data_len() {
+            return Err(error::Error::DataTooLong);
+        }
+
+        wifi.handler()?
+            .start_client_by_ip(ip, port, self.socket, types::ProtocolMode::Udp)?;
+        let allocated = wifi
+            .sockets
+            .get(self.socket)
+            .is_some_and(|info| info.state == types::SocketState::Allocated);
+        if allocated {
+            wifi.sockets.set_started(
+                self.socket,
+                types::ProtocolMode::Udp,
+                types::SocketState::Bound,
+            );
+        }
+        wifi.handler()?.insert_data_buf(self.socket, data)?;
+        wifi.handler()?.send_udp_data(self.socket)
+    }
+
+    pub fn recv_from(
+        &mut self,
+        wifi: &mut Wifi<T>,
+        data: &mut [u8],
+    ) -> nb::Result<(usize, types::RemoteData), error::Error<T::Error>> {
+        // Polling the available data makes the firmware parse the next datagram, if any, so it is
+        // only polled once per datagram
+        let size = usize::from(wifi.handler()?.avail_data(self.socket)?);
+        if size == 0 {
+            return Err(nb::Error::WouldBlock);
+        }
+
+        let remote_data = wifi.handler()?.get_remote_data(self.socket)?;
+        let len = data.len().min(size);
+        let mut received = 0;
+        while received < len {
+            let end = len.min(received + wifi.max_data_len());
+            match wifi
+                .handler()?
+                .get_data_buf(self.socket, &mut data[received..end])?
+            {
+                0 => break,
+                n => received += n,
+            }
+        }
+
+        // Like with std, the rest of a datagram that doesn't fit is discarded rather than
+        // received as the next one
+        let mut discarded = [0; 64];
+        let mut remaining = size - received;
+        while remaining > 0 {
+            let len = remaining.min(discarded.len()).min(wifi.max_data_len());
+            match wifi
+                .handler()?
+                .get_data_buf(self.socket, &mut discarded[..len])?
+            {
+                0 => break,
+                n => remaining = remaining.saturating_sub(n),
+            }
+        }
+
+        Ok((received, remote_data))
+    }
+
+    pub fn socket(&self) -> types::Socket {
+        self.socket
+    }
+
+    pub fn close(self, wifi: &mut Wifi<T>) -> Result<(), error::Error<T::Error>> {
+        wifi.close_socket(self.socket)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::Wifi;
+    use crate::command::Command;
+    use crate::error::{Error, ProtocolError};
+    use crate::transport::{MockError, MockTransport};
+    use crate::types;
+    use std::vec;
+    use std::vec::Vec;
+
+    #[test]
+    fn scan_networks() {
+        let mut mock = MockTransport::new();
+        mock.expect(Command::StartScanNetworks, &[&[1]])
+            .expect(Command::ScanNetworks, &[b"home", b"cafe"])
+            .expect(Command::GetIdxRssiCmd, &[&(-40i32).to_le_bytes()])
+            .expect(Command::GetIdxEnctCmd, &[&[7]])
+            .expect(Command::GetIdxBssid, &[&[1, 2, 3, 4, 5, 6]])
+            .expect(Command::GetIdxChannelCmd, &[&[6]])
+            .expect(Command::GetIdxRssiCmd, &[&(-70i32).to_le_bytes()])
+            .expect(Command::GetIdxEnctCmd, &[&[2]])
+            .expect(Command::GetIdxBssid, &[&[1, 2, 3, 4, 5, 7]])
+            .expect(Command::GetIdxChannelCmd, &[&[11]]);
+        let mut wifi = Wifi::new(mock);
+
+        let networks = wifi
+            .scan_networks()
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(networks.len(), 2);
+        assert_eq!(&networks[0].ssid[..], b"home");
+        assert_eq!(networks[0].rssi, -40);
+        assert_eq!(networks[0].encryption_type, types::EncryptionType::Wpa2Psk);
+        assert_eq!(networks[0].bssid, [1, 2, 3, 4, 5, 6]);
+        assert_eq!(networks[0].channel, 6);
+        assert_eq!(&networks[1].ssid[..], b"cafe");
+        assert_eq!(networks[1].rssi, -70);
+        assert_eq!(
+            networks[1].encryption_type,
+            types::EncryptionType::OpenSystem
+        );
+        assert_eq!(networks[1].channel, 11);
+        assert_eq!(wifi.transport().pending(), 0);
+        assert_eq!(wifi.transport().calls()[6].params(), vec![&[1][..]]);
+    }
+
+    #[test]
+    fn client_recv_buffers_data() {
+        let mut mock = MockTransport::new();
+        mock.expect(Command::GetSocketCmd, &[&[3]])
+            .expect(Command::GetDatabufTcpCmd, &[b"hello world"]);
+        let mut wifi = Wifi::new(mock);
+        let mut client = wifi.new_client().unwrap();
+
+        let mut buf = [0; 5];
+        assert_eq!(client.recv(&mut wifi, &mut buf), Ok(5));
+        assert_eq!(&buf, b"hello");
+        assert_eq!(client.recv(&mut wifi, &mut buf), Ok(5));
+        assert_eq!(&buf, b" worl");
+        assert_eq!(client.recv(&mut wifi, &mut buf), Ok(1));
+        assert_eq!(&buf[..1], b"d");
+
+        // All of it came from a single read of the firmware's buffer
+        let calls = wifi.transport().calls();
+        assert_eq!(calls.len(), 2);
+        assert!(calls[1].long_send && calls[1].long_recv);
+        assert_eq!(
+            calls[1].params(),
+            vec![&[3][..], &4096u16.to_le_bytes()[..]]
+        );
+    }
+
+    #[test]
+    fn client_recv_within_data_limit() {
+        let mut mock = MockTransport::new();
+        mock.set_max_data_len(8)
+            .expect(Command::GetSocketCmd, &[&[3]])
+            .expect(Command::GetDatabufTcpCmd, &[b"hello wo"]);
+        let mut wifi = Wifi::new(mock);
+        let mut client = wifi.new_client().unwrap();
+
+        let mut buf = [0; 5];
+        assert_eq!(client.recv(&mut wifi, &mut buf), Ok(5));
+        assert_eq!(
+            wifi.transport().calls()[1].params(),
+            vec![&[3][..], &8u16.to_le_bytes()[..]]
+        );
+    }
+
+    #[test]
+    fn client_recv_directly() {
+        let mut mock = MockTransport::new();
+        mock.expect(Command::GetSocketCmd, &[&[3]])
+            .expect(Command::GetDatabufTcpCmd, &[b"hello"]);
+        let mut wifi = Wifi::new(mock);
+        let mut client = wifi.new_client_with_buffer::<8>().unwrap();
+
+        // A slice as large as the buffer is read straight into
+        let mut buf = [0; 8];
+        assert_eq!(client.recv(&mut wifi, &mut buf), Ok(5));
+        assert_eq!(&buf[..5], b"hello");
+        assert_eq!(
+            wifi.transport().calls()[1].params(),
+            vec![&[3][..], &8u16.to_le_bytes()[..]]
+        );
+        assert_eq!(wifi.transport().calls().len(), 2);
+    }
+
+    #[test]
+    fn try_recv_empty_slice() {
+        let mut mock = MockTransport::new();
+        mock.expect(Command::GetSocketCmd, &[&[3]])
+            .expect(Command::AvailDataTcpCmd, &[&5u16.to_le_bytes()])
+            .expect(Command::AvailDataTcpCmd, &[&0u16.to_le_bytes()])
+            .expect(Command::GetClientStateTcpCmd, &[&[7]])
+            .expect(Command::AvailDataTcpCmd, &[&0u16.to_le_bytes()]);
+        let mut wifi = Wifi::new(mock);
+        let mut client = wifi.new_client().unwrap();
+
+        // Only a closed connection gives `Ok(0)`, even for an empty slice
+        assert_eq!(
+            client.try_recv(&mut wifi, &mut []),
+            Err(nb::Error::WouldBlock)
+        );
+        assert_eq!(client.try_recv(&mut wifi, &mut []), Ok(0));
+        assert_eq!(wifi.transport().pending(), 0);
+    }
+
+    #[test]
+    fn set_passphrase_failure() {
+        let mut mock = MockTransport::new();
+        mock.expect(Command::SetPassphraseCmd, &[&[0]]);
+        let mut wifi = Wifi::new(mock);
+
+        let config = types::Config::Station(types::StationConfig {
+            network: types::NetworkConfig::Password {
+                ssid: b"home",
+                password: b"secret",
+            },
+            ip_config: None,
+        });
+
+        assert_eq!(wifi.configure(config, None), Err(Error::SetPassphrase));
+        assert_eq!(
+            wifi.transport().calls()[0].params(),
+            vec![&b"home\0"[..], &b"secret\0"[..]]
+        );
+    }
+
+    #[test]
+    fn oversized_param_is_not_sent() {
+        let mut wifi = Wifi::new(MockTransport::new());
+        let config = types::Config::Station(types::StationConfig {
+            network: types::NetworkConfig::Password {
+                ssid: b"home",
+                password: &[b'x'; 300],
+            },
+            ip_config: None,
+        });
+
+        assert_eq!(
+            wifi.configure(config, None),
+            Err(Error::Protocol(ProtocolError::Overflow {
+                capacity: 255,
+                actual: 301
+            }))
+        );
+        assert!(wifi.transport().calls().is_empty());
+    }
+
+    #[test]
+    fn malformed_reply() {
+        let mut mock = MockTransport::new();
+        mock.expect(Command::GetCurrRssiCmd, &[&[0xc4, 0xff]]);
+        let mut wifi = Wifi::new(mock);
+
+        assert_eq!(
+            wifi.rssi(),
+            Err(Error::Protocol(ProtocolError::LengthMismatch {
+                expected: 4,
+                actual: 2
+            }))
+        );
+    }
+
+    #[test]
+    fn no_free_socket() {
+        let mut mock = MockTransport::new();
+        mock.expect(Command::GetSocketCmd, &[&[255]]);
+        let mut wifi = Wifi::new(mock);
+
+        assert_eq!(wifi.new_client().err(), Some(Error::NoFreeSocket));
+        assert_eq!(wifi.available_sockets(), crate::sockets::MAX_SOCKETS);
+    }
+
+    #[test]
+    fn accept_reports_each_client_once() {
+        let mut mock = MockTransport::new();
+        mock.expect(Command::GetSocketCmd, &[&[0]])
+            .expect(Command::StartServerTcpCmd, &[&[1]])
+            .expect(Command::AvailDataTcpCmd, &[&1u16.to_le_bytes()])
+            // Still reported while the accepted client has unread data
+            .expect(Command::AvailDataTcpCmd, &[&1u16.to_le_bytes()]);
+        let mut wifi = Wifi::new(mock);
+        let mut server = wifi.new_server().unwrap();
+        server
+            .listen(&mut wifi, 8080, types::ProtocolMode::Tcp)
+            .unwrap();
+
+        let client = server.accept(&mut wifi).unwrap();
+        assert_eq!(client.socket(), types::Socket(1));
+        assert_eq!(server.accept(&mut wifi).err(), Some(nb::Error::WouldBlock));
+    }
+
+    #[test]
+    fn accept_refuses_socket_of_unstarted_client() {
+        let mut mock = MockTransport::new();
+        mock.expect(Command::GetSocketCmd, &[&[0]])
+            .expect(Command::StartServerTcpCmd, &[&[1]])
+            .expect(Command::GetSocketCmd, &[&[1]])
+            .expect(Command::AvailDataTcpCmd, &[&1u16.to_le_bytes()])
+            .expect(Command::StopClientTcpCmd, &[&[1]]);
+        let mut wifi = Wifi::new(mock);
+        let mut server = wifi.new_server().unwrap();
+        server
+            .listen(&mut wifi, 8080, types::ProtocolMode::Tcp)
+            .unwrap();
+        let client = wifi.new_client().unwrap();
+
+        assert_eq!(
+            server.accept(&mut wifi).err(),
+            Some(nb::Error::Other(Error::SocketInUse(client.socket())))
+        );
+        assert_eq!(
+            wifi.transport().calls()[4].params(),
+            vec![&[client.socket().0][..]]
+        );
+        assert_eq!(
+            wifi.sockets().find(|info| info.socket == client.socket()),
+            Some(types::SocketInfo {
+                socket: client.socket(),
+                kind: types::SocketKind::Client,
+                protocol: None,
+                state: types::SocketState::Allocated,
+            })
+        );
+    }
+
+    #[test]
+    fn reclaim_sockets_keeps_live_handles() {
+        let mut mock = MockTransport::new();
+        mock.expect(Command::GetSocketCmd, &[&[0]])
+            .expect(Command::GetSocketCmd, &[&[1]])
+            .expect(Command::StopClientTcpCmd, &[&[1]]);
+        let mut wifi = Wifi::new(mock);
+        assert_eq!(wifi.reclaim_sockets(), Err(Error::Unsupported));
+
+        wifi.set_socket_leases(std::boxed::Box::leak(std::boxed::Box::default()));
+        let kept = wifi.new_client().unwrap();
+        let dropped = wifi.new_client().unwrap();
+        let dropped_socket = dropped.socket();
+        drop(dropped);
+
+        assert_eq!(wifi.reclaim_sockets(), Ok(1));
+        assert_eq!(
+            wifi.transport().calls()[2].params(),
+            vec![&[dropped_socket.0][..]]
+        );
+        assert_eq!(
+            wifi.sockets().map(|info| info.socket).collect::<Vec<_>>(),
+            vec![kept.socket()]
+        );
+        assert_eq!(wifi.reclaim_sockets(), Ok(0));
+    }
+
+    #[test]
+    fn recovery_retries_after_desync() {
+        let mut mock = MockTransport::new();
+        mock.expect_error(Command::GetCurrRssiCmd, MockError::Desync)
+            .expect(Command::GetCurrRssiCmd, &[&(-50i32).to_be_bytes()])
+            .expect_error(Command::SetNetCmd, MockError::Desync);
+        let mut wifi = Wifi::new(mock);
+        wifi.set_recovery(types::RecoveryConfig {
+            retries: 1,
+            reset_after: None,
+        });
+
+        assert_eq!(wifi.rssi(), Ok(-50));
+        // Commands with side effects are resynced but not retried
+        let config = types::Config::Station(types::StationConfig {
+            network: types::NetworkConfig::Open { ssid: b"home" },
+            ip_config: None,
+        });
+        assert_eq!(
+            wifi.configure(config, None),
+            Err(Error::Transport(MockError::Desync))
+        );
+        assert_eq!(wifi.transport().resyncs(), 2);
+        assert_eq!(wifi.transport().pending(), 0);
+    }
 }
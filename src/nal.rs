@@ -0,0 +1,243 @@
+use crate::error;
+use crate::transport;
+use crate::types;
+use core::net;
+
+#[derive(Debug)]
+pub struct TcpSocket<T> {
+    client: crate::Client<T>,
+    local_port: Option<u16>,
+}
+
+#[derive(Debug)]
+pub struct UdpSocket<T> {
+    socket: crate::UdpSocket<T>,
+    remote: Option<net::SocketAddrV4>,
+}
+
+impl<T> TcpSocket<T> {
+    pub fn socket(&self) -> types::Socket {
+        self.client.socket
+    }
+}
+
+impl<T> UdpSocket<T> {
+    pub fn socket(&self) -> types::Socket {
+        self.socket.socket
+    }
+}
+
+impl<E> embedded_nal::TcpError for error::Error<E>
+where
+    E: core::fmt::Debug,
+{
+    fn kind(&self) -> embedded_nal::TcpErrorKind {
+        match self {
+            error::Error::ConnectionClosed => embedded_nal::TcpErrorKind::PipeClosed,
+            _ => embedded_nal::TcpErrorKind::Other,
+        }
+    }
+}
+
+impl<T> embedded_nal::TcpClientStack for crate::Wifi<T>
+where
+    T: transport::Transport,
+    T::Error: core::fmt::Debug,
+{
+    type TcpSocket = TcpSocket<T>;
+    type Error = error::Error<T::Error>;
+
+    fn socket(&mut self) -> Result<Self::TcpSocket, Self::Error> {
+        let client = self.new_client()?;
+        let local_port = None;
+        Ok(TcpSocket { client, local_port })
+    }
+
+    fn connect(
+        &mut self,
+        socket: &mut Self::TcpSocket,
+        remote: net::SocketAddr,
+    ) -> nb::Result<(), Self::Error> {
+        let remote = to_socket_addr_v4(remote)?;
+        socket.client.connect_ipv4(
+            self,
+            from_ipv4_addr(*remote.ip()),
+            remote.port(),
+            types::ProtocolMode::Tcp,
+        )?;
+        Ok(())
+    }
+
+    fn send(
+        &mut self,
+        socket: &mut Self::TcpSocket,
+        buffer: &[u8],
+    ) -> nb::Result<usize, Self::Error> {
+        Ok(socket.client.send(self, buffer)?)
+    }
+
+    fn receive(
+        &mut self,
+        socket: &mut Self::TcpSocket,
+        buffer: &mut [u8],
+    ) -> nb::Result<usize, Self::Error> {
+        match socket.client.try_recv(self, buffer)? {
+            0 => Err(nb::Error::Other(error::Error::ConnectionClosed)),
+            len => Ok(len),
+        }
+    }
+
+    fn close(&mut self, socket: Self::TcpSocket) -> Result<(), Self::Error> {
+        socket.client.close(self)
+    }
+}
+
+impl<T> embedded_nal::TcpFullStack for crate::Wifi<T>
+where
+    T: transport::Transport,
+    T::Error: core::fmt::Debug,
+{
+    fn bind(&mut self, socket: &mut Self::TcpSocket, local_port: u16) -> Result<(), Self::Error> {
+        socket.local_port = Some(local_port);
+        Ok(())
+    }
+
+    fn listen(&mut self, socket: &mut Self::TcpSocket) -> Result<(), Self::Error> {
+        let local_port = socket.local_port.ok_or(error::Error::NotConnected)?;
+        self.listen_socket(socket.client.socket, local_port, types::ProtocolMode::Tcp)
+    }
+
+    fn accept(
+        &mut self,
+        socket: &mut Self::TcpSocket,
+    ) -> nb::Result<(Self::TcpSocket, net::SocketAddr), Self::Error> {
+        let (client_socket, lease) = self.accept_socket(socket.client.socket)?;
+        let client = crate::Client::new(client_socket, lease);
+        let remote_data = self.handler()?.get_remote_data(client_socket)?;
+
+        let local_port = socket.local_port;
+        let remote = to_socket_addr(remote_data);
+
+        Ok((TcpSocket { client, local_port }, remote))
+    }
+}
+
+impl<T> embedded_nal::UdpClientStack for crate::Wifi<T>
+where
+    T: transport::Transport,
+    T::Error: core::fmt::Debug,
+{
+    type UdpSocket = UdpSocket<T>;
+    type Error = error::Error<T::Error>;
+
+    fn socket(&mut self) -> Result<Self::UdpSocket, Self::Error> {
+        let socket = self.new_udp_socket()?;
+        let remote = None;
+        Ok(UdpSocket { socket, remote })
+    }
+
+    fn connect(
+        &mut self,
+        socket: &mut Self::UdpSocket,
+        remote: net::SocketAddr,
+    ) -> Result<(), Self::Error> {
+        socket.remote = Some(to_socket_addr_v4(remote)?);
+        Ok(())
+    }
+
+    fn send(&mut self, socket: &mut Self::UdpSocket, buffer: &[u8]) -> nb::Result<(), Self::Error> {
+        let remote = socket.remote.ok_or(error::Error::NotConnected)?;
+        socket
+            .socket
+            .send_to(self, buffer, from_ipv4_addr(*remote.ip()), remote.port())?;
+        Ok(())
+    }
+
+    fn receive(
+        &mut self,
+        socket: &mut Self::UdpSocket,
+        buffer: &mut [u8],
+    ) -> nb::Result<(usize, net::SocketAddr), Self::Error> {
+        let (len, remote_data) = socket.socket.recv_from(self, buffer)?;
+        Ok((len, to_socket_addr(remote_data)))
+    }
+
+    fn close(&mut self, socket: Self::UdpSocket) -> Result<(), Self::Error> {
+        socket.socket.close(self)
+    }
+}
+
+impl<T> embedded_nal::UdpFullStack for crate::Wifi<T>
+where
+    T: transport::Transport,
+    T::Error: core::fmt::Debug,
+{
+    fn bind(&mut self, socket: &mut Self::UdpSocket, local_port: u16) -> Result<(), Self::Error> {
+        socket.socket.bind(self, local_port)
+    }
+
+    fn send_to(
+        &mut self,
+        socket: &mut Self::UdpSocket,
+        remote: net::SocketAddr,
+        buffer: &[u8],
+    ) -> nb::Result<(), Self::Error> {
+        let remote = to_socket_addr_v4(remote)?;
+        socket
+            .socket
+            .send_to(self, buffer, from_ipv4_addr(*remote.ip()), remote.port())?;
+        Ok(())
+    }
+}
+
+impl<T> embedded_nal::Dns for crate::Wifi<T>
+where
+    T: transport::Transport,
+    T::Error: core::fmt::Debug,
+{
+    type Error = error::Error<T::Error>;
+
+    fn get_host_by_name(
+        &mut self,
+        hostname: &str,
+        addr_type: embedded_nal::AddrType,
+    ) -> nb::Result<net::IpAddr, Self::Error> {
+        if addr_type == embedded_nal::AddrType::IPv6 {
+            return Err(nb::Error::Other(error::Error::Unsupported));
+        }
+
+        let ip = self.resolve(hostname)?;
+        Ok(net::IpAddr::V4(to_ipv4_addr(ip)))
+    }
+
+    fn get_host_by_address(
+        &mut self,
+        _addr: net::IpAddr,
+        _result: &mut [u8],
+    ) -> nb::Result<usize, Self::Error> {
+        // The firmware has no reverse DNS lookup
+        Err(nb::Error::Other(error::Error::Unsupported))
+    }
+}
+
+fn to_socket_addr_v4<E>(addr: net::SocketAddr) -> Result<net::SocketAddrV4, error::Error<E>> {
+    match addr {
+        net::SocketAddr::V4(addr) => Ok(addr),
+        net::SocketAddr::V6(_) => Err(error::Error::Unsupported),
+    }
+}
+
+fn to_socket_addr(remote_data: types::RemoteData) -> net::SocketAddr {
+    net::SocketAddr::V4(net::SocketAddrV4::new(
+        to_ipv4_addr(remote_data.ip),
+        remote_data.port,
+    ))
+}
+
+fn to_ipv4_addr(ip: no_std_net::Ipv4Addr) -> net::Ipv4Addr {
+    net::Ipv4Addr::from(ip.octets())
+}
+
+fn from_ipv4_addr(ip: net::Ipv4Addr) -> no_std_net::Ipv4Addr {
+    no_std_net::Ipv4Addr::from(ip.octets())
+}
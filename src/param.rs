@@ -1,5 +1,5 @@
-use super::full_duplex::FullDuplexExt as _;
 use crate::encoding;
+use crate::error;
 use core::marker;
 
 pub trait SendParam {
@@ -9,30 +9,36 @@ pub trait SendParam {
         self.len() + if long { 2 } else { 1 }
     }
 
-    fn send<S>(&self, spi: &mut S) -> Result<(), S::Error>
+    fn send<S>(&self, sink: &mut S) -> Result<(), S::Error>
     where
-        S: embedded_hal::spi::FullDuplex<u8>;
+        S: encoding::Sink;
 
-    fn send_length_delimited<S>(&self, spi: &mut S, long: bool) -> Result<(), S::Error>
+    fn send_length_delimited<S>(&self, sink: &mut S, long: bool) -> Result<(), S::Error>
     where
-        S: embedded_hal::spi::FullDuplex<u8>,
+        S: encoding::Sink,
     {
-        encoding::send_len(spi, long, self.len())?;
-        self.send(spi)
+        encoding::send_len(sink, long, self.len())?;
+        self.send(sink)
     }
 }
 
 pub trait RecvParam {
-    fn recv<S>(&mut self, spi: &mut S, len: usize) -> Result<(), S::Error>
+    fn capacity(&self) -> usize;
+
+    fn capacity_length_delimited(&self, long: bool) -> usize {
+        self.capacity() + if long { 2 } else { 1 }
+    }
+
+    fn recv<S>(&mut self, source: &mut S, len: usize) -> Result<(), S::Error>
     where
-        S: embedded_hal::spi::FullDuplex<u8>;
+        S: encoding::Source;
 
-    fn recv_length_delimited<S>(&mut self, spi: &mut S, long: bool) -> Result<(), S::Error>
+    fn recv_length_delimited<S>(&mut self, source: &mut S, long: bool) -> Result<(), S::Error>
     where
-        S: embedded_hal::spi::FullDuplex<u8>,
+        S: encoding::Source,
     {
-        let len = encoding::recv_len(spi, long)?;
-        self.recv(spi, len)
+        let len = encoding::recv_len(source, long)?;
+        self.recv(source, len)
     }
 }
 
@@ -60,11 +66,11 @@ where
         (*self).len()
     }
 
-    fn send<S>(&self, spi: &mut S) -> Result<(), S::Error>
+    fn send<S>(&self, sink: &mut S) -> Result<(), S::Error>
     where
-        S: embedded_hal::spi::FullDuplex<u8>,
+        S: encoding::Sink,
     {
-        (*self).send(spi)
+        (*self).send(sink)
     }
 }
 
@@ -72,11 +78,15 @@ impl<A> RecvParam for &mut A
 where
     A: RecvParam + ?Sized,
 {
-    fn recv<S>(&mut self, spi: &mut S, len: usize) -> Result<(), S::Error>
+    fn capacity(&self) -> usize {
+        (**self).capacity()
+    }
+
+    fn recv<S>(&mut self, source: &mut S, len: usize) -> Result<(), S::Error>
     where
-        S: embedded_hal::spi::FullDuplex<u8>,
+        S: encoding::Source,
     {
-        (*self).recv(spi, len)
+        (*self).recv(source, len)
     }
 }
 
@@ -85,22 +95,26 @@ impl SendParam for u8 {
         1
     }
 
-    fn send<S>(&self, spi: &mut S) -> Result<(), S::Error>
+    fn send<S>(&self, sink: &mut S) -> Result<(), S::Error>
     where
-        S: embedded_hal::spi::FullDuplex<u8>,
+        S: encoding::Sink,
     {
-        spi.send_exchange(*self)?;
+        sink.send_byte(*self)?;
         Ok(())
     }
 }
 
 impl RecvParam for u8 {
-    fn recv<S>(&mut self, spi: &mut S, len: usize) -> Result<(), S::Error>
+    fn capacity(&self) -> usize {
+        1
+    }
+
+    fn recv<S>(&mut self, source: &mut S, len: usize) -> Result<(), S::Error>
     where
-        S: embedded_hal::spi::FullDuplex<u8>,
+        S: encoding::Source,
     {
-        assert_eq!(1, len);
-        *self = spi.recv_exchange()?;
+        expect_len(1, len)?;
+        *self = source.recv_byte()?;
         Ok(())
     }
 }
@@ -113,14 +127,14 @@ where
         2
     }
 
-    fn send<S>(&self, spi: &mut S) -> Result<(), S::Error>
+    fn send<S>(&self, sink: &mut S) -> Result<(), S::Error>
     where
-        S: embedded_hal::spi::FullDuplex<u8>,
+        S: encoding::Sink,
     {
         let mut buf = [0; 2];
         O::write_u16(&mut buf, self.value);
-        spi.send_exchange(buf[0])?;
-        spi.send_exchange(buf[1])?;
+        sink.send_byte(buf[0])?;
+        sink.send_byte(buf[1])?;
         Ok(())
     }
 }
@@ -129,14 +143,18 @@ impl<O> RecvParam for Scalar<O, u16>
 where
     O: byteorder::ByteOrder,
 {
-    fn recv<S>(&mut self, spi: &mut S, len: usize) -> Result<(), S::Error>
+    fn capacity(&self) -> usize {
+        2
+    }
+
+    fn recv<S>(&mut self, source: &mut S, len: usize) -> Result<(), S::Error>
     where
-        S: embedded_hal::spi::FullDuplex<u8>,
+        S: encoding::Source,
     {
-        assert_eq!(2, len);
+        expect_len(2, len)?;
         let mut buf = [0; 2];
-        buf[0] = spi.recv_exchange()?;
-        buf[1] = spi.recv_exchange()?;
+        buf[0] = source.recv_byte()?;
+        buf[1] = source.recv_byte()?;
         self.value = O::read_u16(&buf);
         Ok(())
     }
@@ -150,16 +168,16 @@ where
         4
     }
 
-    fn send<S>(&self, spi: &mut S) -> Result<(), S::Error>
+    fn send<S>(&self, sink: &mut S) -> Result<(), S::Error>
     where
-        S: embedded_hal::spi::FullDuplex<u8>,
+        S: encoding::Sink,
     {
         let mut buf = [0; 4];
         O::write_u32(&mut buf, self.value);
-        spi.send_exchange(buf[0])?;
-        spi.send_exchange(buf[1])?;
-        spi.send_exchange(buf[2])?;
-        spi.send_exchange(buf[3])?;
+        sink.send_byte(buf[0])?;
+        sink.send_byte(buf[1])?;
+        sink.send_byte(buf[2])?;
+        sink.send_byte(buf[3])?;
         Ok(())
     }
 }
@@ -168,16 +186,20 @@ impl<O> RecvParam for Scalar<O, u32>
 where
     O: byteorder::ByteOrder,
 {
-    fn recv<S>(&mut self, spi: &mut S, len: usize) -> Result<(), S::Error>
+    fn capacity(&self) -> usize {
+        4
+    }
+
+    fn recv<S>(&mut self, source: &mut S, len: usize) -> Result<(), S::Error>
     where
-        S: embedded_hal::spi::FullDuplex<u8>,
+        S: encoding::Source,
     {
-        assert_eq!(4, len);
+        expect_len(4, len)?;
         let mut buf = [0; 4];
-        buf[0] = spi.recv_exchange()?;
-        buf[1] = spi.recv_exchange()?;
-        buf[2] = spi.recv_exchange()?;
-        buf[3] = spi.recv_exchange()?;
+        buf[0] = source.recv_byte()?;
+        buf[1] = source.recv_byte()?;
+        buf[2] = source.recv_byte()?;
+        buf[3] = source.recv_byte()?;
         self.value = O::read_u32(&buf);
         Ok(())
     }
@@ -188,12 +210,12 @@ impl SendParam for [u8] {
         self.len()
     }
 
-    fn send<S>(&self, spi: &mut S) -> Result<(), S::Error>
+    fn send<S>(&self, sink: &mut S) -> Result<(), S::Error>
     where
-        S: embedded_hal::spi::FullDuplex<u8>,
+        S: encoding::Sink,
     {
         for &byte in self.iter() {
-            spi.send_exchange(byte)?;
+            sink.send_byte(byte)?;
         }
 
         Ok(())
@@ -208,27 +230,57 @@ where
         self.len()
     }
 
-    fn send<S>(&self, spi: &mut S) -> Result<(), S::Error>
+    fn send<S>(&self, sink: &mut S) -> Result<(), S::Error>
     where
-        S: embedded_hal::spi::FullDuplex<u8>,
+        S: encoding::Sink,
     {
-        SendParam::send(self.as_slice(), spi)
+        SendParam::send(self.as_slice(), sink)
     }
 }
 
 impl RecvParam for &mut [u8] {
-    fn recv<S>(&mut self, spi: &mut S, len: usize) -> Result<(), S::Error>
+    fn capacity(&self) -> usize {
+        self.len()
+    }
+
+    fn recv<S>(&mut self, source: &mut S, len: usize) -> Result<(), S::Error>
     where
-        S: embedded_hal::spi::FullDuplex<u8>,
+        S: encoding::Source,
     {
         use core::mem;
 
+        if len > self.len() {
+            return Err(error::ProtocolError::Overflow {
+                capacity: self.len(),
+                actual: len,
+            }
+            .into());
+        }
+
         for i in 0..len {
-            self[i] = spi.recv_exchange()?;
+            self[i] = source.recv_byte()?;
         }
 
-        let slice = mem::replace(self, &mut []);
-        *self = &mut slice[..len as usize];
+        let slice = mem::take(self);
+        *self = &mut slice[..len];
+
+        Ok(())
+    }
+}
+
+impl<const N: usize> RecvParam for [u8; N] {
+    fn capacity(&self) -> usize {
+        N
+    }
+
+    fn recv<S>(&mut self, source: &mut S, len: usize) -> Result<(), S::Error>
+    where
+        S: encoding::Source,
+    {
+        expect_len(N, len)?;
+        for byte in self.iter_mut() {
+            *byte = source.recv_byte()?;
+        }
 
         Ok(())
     }
@@ -238,12 +290,25 @@ impl<A> RecvParam for arrayvec::ArrayVec<A>
 where
     A: arrayvec::Array<Item = u8>,
 {
-    fn recv<S>(&mut self, spi: &mut S, len: usize) -> Result<(), S::Error>
+    fn capacity(&self) -> usize {
+        A::CAPACITY
+    }
+
+    fn recv<S>(&mut self, source: &mut S, len: usize) -> Result<(), S::Error>
     where
-        S: embedded_hal::spi::FullDuplex<u8>,
+        S: encoding::Source,
     {
+        if len > A::CAPACITY {
+            return Err(error::ProtocolError::Overflow {
+                capacity: A::CAPACITY,
+                actual: len,
+            }
+            .into());
+        }
+
+        self.clear();
         for _ in 0..len {
-            self.push(spi.recv_exchange()?);
+            self.push(source.recv_byte()?);
         }
 
         Ok(())
@@ -258,12 +323,12 @@ where
         self.0.len() + 1
     }
 
-    fn send<S>(&self, spi: &mut S) -> Result<(), S::Error>
+    fn send<S>(&self, sink: &mut S) -> Result<(), S::Error>
     where
-        S: embedded_hal::spi::FullDuplex<u8>,
+        S: encoding::Sink,
     {
-        self.0.send(spi)?;
-        spi.send_exchange(0)?;
+        self.0.send(sink)?;
+        sink.send_byte(0)?;
         Ok(())
     }
 }
@@ -272,13 +337,27 @@ impl<A> RecvParam for NullTerminated<A>
 where
     A: RecvParam,
 {
-    fn recv<S>(&mut self, spi: &mut S, len: usize) -> Result<(), S::Error>
+    fn capacity(&self) -> usize {
+        self.0.capacity() + 1
+    }
+
+    fn recv<S>(&mut self, source: &mut S, len: usize) -> Result<(), S::Error>
     where
-        S: embedded_hal::spi::FullDuplex<u8>,
+        S: encoding::Source,
     {
-        self.0.recv(spi, len - 1)?;
-        assert_eq!(0, spi.recv_exchange()?);
-        Ok(())
+        if len == 0 {
+            return Err(error::ProtocolError::LengthMismatch {
+                expected: 1,
+                actual: 0,
+            }
+            .into());
+        }
+
+        self.0.recv(source, len - 1)?;
+        match source.recv_byte()? {
+            0 => Ok(()),
+            byte => Err(error::ProtocolError::BadTerminator(byte).into()),
+        }
     }
 }
 
@@ -339,3 +418,11 @@ impl<O, A> core::ops::DerefMut for Scalar<O, A> {
         &mut self.value
     }
 }
+
+fn expect_len(expected: usize, actual: usize) -> Result<(), error::ProtocolError> {
+    if expected == actual {
+        Ok(())
+    } else {
+        Err(error::ProtocolError::LengthMismatch { expected, actual })
+    }
+}
@@ -1,5 +1,6 @@
-use super::full_duplex::FullDuplexExt as _;
 use super::param;
+use crate::encoding;
+use crate::error;
 use crate::param::SendParam;
 
 pub trait SendParams {
@@ -9,15 +10,27 @@ pub trait SendParams {
 
     fn param_len(&self, long: bool) -> usize;
 
-    fn send<S>(&self, spi: &mut S, long: bool) -> Result<(), S::Error>
+    fn send<S>(&self, sink: &mut S, long: bool) -> Result<(), S::Error>
     where
-        S: embedded_hal::spi::FullDuplex<u8>;
+        S: encoding::Sink;
+
+    // Checks that the params can be encoded before any of them is sent, so that a frame is never
+    // cut short
+    fn check(&self, long: bool) -> Result<(), error::ProtocolError> {
+        self.send(&mut encoding::CheckSink, long)
+    }
 }
 
 pub trait RecvParams {
-    fn recv<S>(&mut self, spi: &mut S, long: bool) -> Result<(), S::Error>
+    fn capacity(&self, long: bool) -> usize {
+        self.param_capacity(long) + 1
+    }
+
+    fn param_capacity(&self, long: bool) -> usize;
+
+    fn recv<S>(&mut self, source: &mut S, long: bool) -> Result<(), S::Error>
     where
-        S: embedded_hal::spi::FullDuplex<u8>;
+        S: encoding::Source;
 }
 
 impl SendParams for () {
@@ -25,21 +38,25 @@ impl SendParams for () {
         0
     }
 
-    fn send<S>(&self, spi: &mut S, _long: bool) -> Result<(), S::Error>
+    fn send<S>(&self, sink: &mut S, _long: bool) -> Result<(), S::Error>
     where
-        S: embedded_hal::spi::FullDuplex<u8>,
+        S: encoding::Sink,
     {
-        spi.send_exchange(0)?;
+        sink.send_byte(0)?;
         Ok(())
     }
 }
 
 impl RecvParams for () {
-    fn recv<S>(&mut self, spi: &mut S, _long: bool) -> Result<(), S::Error>
+    fn param_capacity(&self, _long: bool) -> usize {
+        0
+    }
+
+    fn recv<S>(&mut self, source: &mut S, _long: bool) -> Result<(), S::Error>
     where
-        S: embedded_hal::spi::FullDuplex<u8>,
+        S: encoding::Source,
     {
-        assert_eq!(0, spi.recv_exchange()?);
+        expect_param_count(0, source.recv_byte()?)?;
         Ok(())
     }
 }
@@ -53,14 +70,14 @@ where
         a.len_length_delimited(long)
     }
 
-    fn send<S>(&self, spi: &mut S, long: bool) -> Result<(), S::Error>
+    fn send<S>(&self, sink: &mut S, long: bool) -> Result<(), S::Error>
     where
-        S: embedded_hal::spi::FullDuplex<u8>,
+        S: encoding::Sink,
     {
         let (a,) = self;
-        spi.send_exchange(1)?;
+        sink.send_byte(1)?;
         log::trace!("param 0");
-        a.send_length_delimited(spi, long)?;
+        a.send_length_delimited(sink, long)?;
         log::trace!("end");
         Ok(())
     }
@@ -70,14 +87,19 @@ impl<A> RecvParams for (A,)
 where
     A: param::RecvParam,
 {
-    fn recv<S>(&mut self, spi: &mut S, long: bool) -> Result<(), S::Error>
+    fn param_capacity(&self, long: bool) -> usize {
+        let (a,) = self;
+        a.capacity_length_delimited(long)
+    }
+
+    fn recv<S>(&mut self, source: &mut S, long: bool) -> Result<(), S::Error>
     where
-        S: embedded_hal::spi::FullDuplex<u8>,
+        S: encoding::Source,
     {
         let (a,) = self;
-        assert_eq!(1, spi.recv_exchange()?);
+        expect_param_count(1, source.recv_byte()?)?;
         log::trace!("param 0");
-        a.recv_length_delimited(spi, long)?;
+        a.recv_length_delimited(source, long)?;
         log::trace!("end");
         Ok(())
     }
@@ -92,16 +114,16 @@ where
         let (a, b) = self;
         a.len_length_delimited(long) + b.len_length_delimited(long)
     }
-    fn send<S>(&self, spi: &mut S, long: bool) -> Result<(), S::Error>
+    fn send<S>(&self, sink: &mut S, long: bool) -> Result<(), S::Error>
     where
-        S: embedded_hal::spi::FullDuplex<u8>,
+        S: encoding::Sink,
     {
         let (a, b) = self;
-        spi.send_exchange(2)?;
+        sink.send_byte(2)?;
         log::trace!("param 0");
-        a.send_length_delimited(spi, long)?;
+        a.send_length_delimited(sink, long)?;
         log::trace!("param 1");
-        b.send_length_delimited(spi, long)?;
+        b.send_length_delimited(sink, long)?;
         log::trace!("end");
         Ok(())
     }
@@ -112,16 +134,21 @@ where
     A: param::RecvParam,
     B: param::RecvParam,
 {
-    fn recv<S>(&mut self, spi: &mut S, long: bool) -> Result<(), S::Error>
+    fn param_capacity(&self, long: bool) -> usize {
+        let (a, b) = self;
+        a.capacity_length_delimited(long) + b.capacity_length_delimited(long)
+    }
+
+    fn recv<S>(&mut self, source: &mut S, long: bool) -> Result<(), S::Error>
     where
-        S: embedded_hal::spi::FullDuplex<u8>,
+        S: encoding::Source,
     {
         let (a, b) = self;
-        assert_eq!(2, spi.recv_exchange()?);
+        expect_param_count(2, source.recv_byte()?)?;
         log::trace!("param 0");
-        a.recv_length_delimited(spi, long)?;
+        a.recv_length_delimited(source, long)?;
         log::trace!("param 1");
-        b.recv_length_delimited(spi, long)?;
+        b.recv_length_delimited(source, long)?;
         log::trace!("end");
         Ok(())
     }
@@ -138,18 +165,18 @@ where
         a.len_length_delimited(long) + b.len_length_delimited(long) + c.len_length_delimited(long)
     }
 
-    fn send<S>(&self, spi: &mut S, long: bool) -> Result<(), S::Error>
+    fn send<S>(&self, sink: &mut S, long: bool) -> Result<(), S::Error>
     where
-        S: embedded_hal::spi::FullDuplex<u8>,
+        S: encoding::Sink,
     {
         let (a, b, c) = self;
-        spi.send_exchange(3)?;
+        sink.send_byte(3)?;
         log::trace!("param 0");
-        a.send_length_delimited(spi, long)?;
+        a.send_length_delimited(sink, long)?;
         log::trace!("param 1");
-        b.send_length_delimited(spi, long)?;
+        b.send_length_delimited(sink, long)?;
         log::trace!("param 2");
-        c.send_length_delimited(spi, long)?;
+        c.send_length_delimited(sink, long)?;
         log::trace!("end");
         Ok(())
     }
@@ -161,18 +188,25 @@ where
     B: param::RecvParam,
     C: param::RecvParam,
 {
-    fn recv<S>(&mut self, spi: &mut S, long: bool) -> Result<(), S::Error>
+    fn param_capacity(&self, long: bool) -> usize {
+        let (a, b, c) = self;
+        a.capacity_length_delimited(long)
+            + b.capacity_length_delimited(long)
+            + c.capacity_length_delimited(long)
+    }
+
+    fn recv<S>(&mut self, source: &mut S, long: bool) -> Result<(), S::Error>
     where
-        S: embedded_hal::spi::FullDuplex<u8>,
+        S: encoding::Source,
     {
         let (a, b, c) = self;
-        assert_eq!(3, spi.recv_exchange()?);
+        expect_param_count(3, source.recv_byte()?)?;
         log::trace!("param 0");
-        a.recv_length_delimited(spi, long)?;
+        a.recv_length_delimited(source, long)?;
         log::trace!("param 1");
-        b.recv_length_delimited(spi, long)?;
+        b.recv_length_delimited(source, long)?;
         log::trace!("param 2");
-        c.recv_length_delimited(spi, long)?;
+        c.recv_length_delimited(source, long)?;
         log::trace!("end");
         Ok(())
     }
@@ -193,20 +227,20 @@ where
             + d.len_length_delimited(long)
     }
 
-    fn send<S>(&self, spi: &mut S, long: bool) -> Result<(), S::Error>
+    fn send<S>(&self, sink: &mut S, long: bool) -> Result<(), S::Error>
     where
-        S: embedded_hal::spi::FullDuplex<u8>,
+        S: encoding::Sink,
     {
         let (a, b, c, d) = self;
-        spi.send_exchange(4)?;
+        sink.send_byte(4)?;
         log::trace!("param 0");
-        a.send_length_delimited(spi, long)?;
+        a.send_length_delimited(sink, long)?;
         log::trace!("param 1");
-        b.send_length_delimited(spi, long)?;
+        b.send_length_delimited(sink, long)?;
         log::trace!("param 2");
-        c.send_length_delimited(spi, long)?;
+        c.send_length_delimited(sink, long)?;
         log::trace!("param 3");
-        d.send_length_delimited(spi, long)?;
+        d.send_length_delimited(sink, long)?;
         log::trace!("end");
         Ok(())
     }
@@ -219,20 +253,28 @@ where
     C: param::RecvParam,
     D: param::RecvParam,
 {
-    fn recv<S>(&mut self, spi: &mut S, long: bool) -> Result<(), S::Error>
+    fn param_capacity(&self, long: bool) -> usize {
+        let (a, b, c, d) = self;
+        a.capacity_length_delimited(long)
+            + b.capacity_length_delimited(long)
+            + c.capacity_length_delimited(long)
+            + d.capacity_length_delimited(long)
+    }
+
+    fn recv<S>(&mut self, source: &mut S, long: bool) -> Result<(), S::Error>
     where
-        S: embedded_hal::spi::FullDuplex<u8>,
+        S: encoding::Source,
     {
         let (a, b, c, d) = self;
-        assert_eq!(4, spi.recv_exchange()?);
+        expect_param_count(4, source.recv_byte()?)?;
         log::trace!("param 0");
-        a.recv_length_delimited(spi, long)?;
+        a.recv_length_delimited(source, long)?;
         log::trace!("param 1");
-        b.recv_length_delimited(spi, long)?;
+        b.recv_length_delimited(source, long)?;
         log::trace!("param 2");
-        c.recv_length_delimited(spi, long)?;
+        c.recv_length_delimited(source, long)?;
         log::trace!("param 3");
-        d.recv_length_delimited(spi, long)?;
+        d.recv_length_delimited(source, long)?;
         log::trace!("end");
         Ok(())
     }
@@ -255,22 +297,22 @@ where
             + e.len_length_delimited(long)
     }
 
-    fn send<S>(&self, spi: &mut S, long: bool) -> Result<(), S::Error>
+    fn send<S>(&self, sink: &mut S, long: bool) -> Result<(), S::Error>
     where
-        S: embedded_hal::spi::FullDuplex<u8>,
+        S: encoding::Sink,
     {
         let (a, b, c, d, e) = self;
-        spi.send_exchange(5)?;
+        sink.send_byte(5)?;
         log::trace!("param 0");
-        a.send_length_delimited(spi, long)?;
+        a.send_length_delimited(sink, long)?;
         log::trace!("param 1");
-        b.send_length_delimited(spi, long)?;
+        b.send_length_delimited(sink, long)?;
         log::trace!("param 2");
-        c.send_length_delimited(spi, long)?;
+        c.send_length_delimited(sink, long)?;
         log::trace!("param 3");
-        d.send_length_delimited(spi, long)?;
+        d.send_length_delimited(sink, long)?;
         log::trace!("param 4");
-        e.send_length_delimited(spi, long)?;
+        e.send_length_delimited(sink, long)?;
         log::trace!("end");
         Ok(())
     }
@@ -284,22 +326,31 @@ where
     D: param::RecvParam,
     E: param::RecvParam,
 {
-    fn recv<S>(&mut self, spi: &mut S, long: bool) -> Result<(), S::Error>
+    fn param_capacity(&self, long: bool) -> usize {
+        let (a, b, c, d, e) = self;
+        a.capacity_length_delimited(long)
+            + b.capacity_length_delimited(long)
+            + c.capacity_length_delimited(long)
+            + d.capacity_length_delimited(long)
+            + e.capacity_length_delimited(long)
+    }
+
+    fn recv<S>(&mut self, source: &mut S, long: bool) -> Result<(), S::Error>
     where
-        S: embedded_hal::spi::FullDuplex<u8>,
+        S: encoding::Source,
     {
         let (a, b, c, d, e) = self;
-        assert_eq!(5, spi.recv_exchange()?);
+        expect_param_count(5, source.recv_byte()?)?;
         log::trace!("param 0");
-        a.recv_length_delimited(spi, long)?;
+        a.recv_length_delimited(source, long)?;
         log::trace!("param 1");
-        b.recv_length_delimited(spi, long)?;
+        b.recv_length_delimited(source, long)?;
         log::trace!("param 2");
-        c.recv_length_delimited(spi, long)?;
+        c.recv_length_delimited(source, long)?;
         log::trace!("param 3");
-        d.recv_length_delimited(spi, long)?;
+        d.recv_length_delimited(source, long)?;
         log::trace!("param 4");
-        e.recv_length_delimited(spi, long)?;
+        e.recv_length_delimited(source, long)?;
         log::trace!("end");
         Ok(())
     }
@@ -314,16 +365,20 @@ where
         self.iter().map(|p| p.len_length_delimited(long)).sum()
     }
 
-    fn send<S>(&self, spi: &mut S, long: bool) -> Result<(), S::Error>
+    fn send<S>(&self, sink: &mut S, long: bool) -> Result<(), S::Error>
     where
-        S: embedded_hal::spi::FullDuplex<u8>,
+        S: encoding::Sink,
     {
         use core::convert::TryFrom;
 
-        spi.send_exchange(u8::try_from(self.len()).unwrap())?;
+        let len = u8::try_from(self.len()).map_err(|_| error::ProtocolError::Overflow {
+            capacity: u8::MAX as usize,
+            actual: self.len(),
+        })?;
+        sink.send_byte(len)?;
         for (i, item) in self.iter().enumerate() {
             log::trace!("param {}", i);
-            item.send_length_delimited(spi, long)?;
+            item.send_length_delimited(sink, long)?;
         }
         log::trace!("end");
         Ok(())
@@ -335,20 +390,88 @@ where
     A: arrayvec::Array,
     A::Item: param::RecvParam + Default,
 {
-    fn recv<S>(&mut self, spi: &mut S, long: bool) -> Result<(), S::Error>
+    fn param_capacity(&self, long: bool) -> usize {
+        use crate::param::RecvParam;
+
+        let item: <A as arrayvec::Array>::Item = Default::default();
+        A::CAPACITY * item.capacity_length_delimited(long)
+    }
+
+    fn recv<S>(&mut self, source: &mut S, long: bool) -> Result<(), S::Error>
     where
-        S: embedded_hal::spi::FullDuplex<u8>,
+        S: encoding::Source,
     {
         use crate::param::RecvParam;
 
-        let len = spi.recv_exchange()?;
+        let len = source.recv_byte()?;
+        if len as usize > A::CAPACITY {
+            return Err(error::ProtocolError::Overflow {
+                capacity: A::CAPACITY,
+                actual: len as usize,
+            }
+            .into());
+        }
+
+        self.clear();
         for i in 0..len {
             log::trace!("param {}", i);
             let mut item: <A as arrayvec::Array>::Item = Default::default();
-            item.recv_length_delimited(spi, long)?;
+            item.recv_length_delimited(source, long)?;
             self.push(item);
         }
         log::trace!("end");
         Ok(())
     }
 }
+
+// The params of the commands that join or open a network, where the passphrase is only sent for a
+// protected network and the channel only when opening an access point
+#[derive(Clone, Copy, Debug)]
+pub struct Network<'a> {
+    pub ssid: &'a [u8],
+    pub passphrase: Option<&'a [u8]>,
+    pub channel: Option<u8>,
+}
+
+impl SendParams for Network<'_> {
+    fn param_len(&self, long: bool) -> usize {
+        param::NullTerminated::new(self.ssid).len_length_delimited(long)
+            + self.passphrase.map_or(0, |passphrase| {
+                param::NullTerminated::new(passphrase).len_length_delimited(long)
+            })
+            + self
+                .channel
+                .map_or(0, |channel| channel.len_length_delimited(long))
+    }
+
+    fn send<S>(&self, sink: &mut S, long: bool) -> Result<(), S::Error>
+    where
+        S: encoding::Sink,
+    {
+        let count = 1 + self.passphrase.is_some() as u8 + self.channel.is_some() as u8;
+        sink.send_byte(count)?;
+        log::trace!("ssid");
+        param::NullTerminated::new(self.ssid).send_length_delimited(sink, long)?;
+        if let Some(passphrase) = self.passphrase {
+            log::trace!("passphrase");
+            param::NullTerminated::new(passphrase).send_length_delimited(sink, long)?;
+        }
+        if let Some(channel) = self.channel {
+            log::trace!("channel");
+            channel.send_length_delimited(sink, long)?;
+        }
+        log::trace!("end");
+        Ok(())
+    }
+}
+
+fn expect_param_count(expected: usize, actual: u8) -> Result<(), error::ProtocolError> {
+    if expected == actual as usize {
+        Ok(())
+    } else {
+        Err(error::ProtocolError::BadParamCount {
+            expected,
+            actual: actual as usize,
+        })
+    }
+}
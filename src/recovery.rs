@@ -0,0 +1,49 @@
+use crate::command;
+use crate::types;
+
+// What a handler does about a failed command
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Action {
+    Retry,
+    Reset,
+    Fail,
+}
+
+// Counts consecutive failed commands to decide, according to a `RecoveryConfig`, whether a command
+// is retried or the module is reset, so that the blocking and the async handlers recover alike
+#[derive(Debug, Default)]
+pub struct Recovery {
+    config: types::RecoveryConfig,
+    failures: u8,
+}
+
+impl Recovery {
+    pub fn set_config(&mut self, config: types::RecoveryConfig) {
+        self.config = config;
+    }
+
+    pub fn succeeded(&mut self) {
+        self.failures = 0;
+    }
+
+    // Called after `command` failed on its attempt following `retries` retries, where `desync`
+    // tells whether the transport was resynced after the failure
+    pub fn failed(&mut self, command: command::Command, desync: bool, retries: u8) -> Action {
+        self.failures = self.failures.saturating_add(1);
+        if self
+            .config
+            .reset_after
+            .is_some_and(|reset_after| self.failures >= reset_after)
+        {
+            log::debug!("reset after {} failed commands", self.failures);
+            self.failures = 0;
+            return Action::Reset;
+        }
+
+        if desync && command.is_idempotent() && retries < self.config.retries {
+            Action::Retry
+        } else {
+            Action::Fail
+        }
+    }
+}
@@ -0,0 +1,715 @@
+use crate::command;
+use crate::error;
+use crate::param;
+use crate::params;
+use crate::types;
+
+// A command together with its params and how to make sense of the reply, shared by the blocking
+// and the async handlers so that each command is encoded in one place only
+#[derive(Debug)]
+pub struct Request<SP, RP, O, E> {
+    pub command: command::Command,
+    pub send_params: SP,
+    pub recv_params: RP,
+    pub long_send: bool,
+    pub long_recv: bool,
+    finish: fn(RP) -> Result<O, error::Error<E>>,
+}
+
+type Status = (u8,);
+
+impl<SP, RP, O, E> Request<SP, RP, O, E> {
+    fn new(
+        command: command::Command,
+        send_params: SP,
+        recv_params: RP,
+        finish: fn(RP) -> Result<O, error::Error<E>>,
+    ) -> Self {
+        let long_send = false;
+        let long_recv = false;
+        Self {
+            command,
+            send_params,
+            recv_params,
+            long_send,
+            long_recv,
+            finish,
+        }
+    }
+
+    fn long_send(self) -> Self {
+        let long_send = true;
+        Self { long_send, ..self }
+    }
+
+    fn long_recv(self) -> Self {
+        let long_recv = true;
+        Self { long_recv, ..self }
+    }
+
+    // Turns the received params into the result of the command
+    pub fn finish(self) -> Result<O, error::Error<E>> {
+        (self.finish)(self.recv_params)
+    }
+}
+
+fn check_status<E>(status: u8, error: error::Error<E>) -> Result<(), error::Error<E>> {
+    if status == 1 {
+        Ok(())
+    } else {
+        Err(error)
+    }
+}
+
+pub fn get_connection_state<E>() -> Request<(), Status, types::ConnectionState, E> {
+    use core::convert::TryFrom;
+
+    Request::new(command::Command::GetConnStatusCmd, (), (0,), |(status,)| {
+        types::ConnectionState::try_from(status).map_err(error::Error::BadConnectionStatus)
+    })
+}
+
+pub fn get_firmware_version<E>() -> Request<
+    (u8,),
+    (param::NullTerminated<arrayvec::ArrayVec<[u8; 16]>>,),
+    arrayvec::ArrayVec<[u8; 16]>,
+    E,
+> {
+    Request::new(
+        command::Command::GetFwVersionCmd,
+        (0,),
+        (param::NullTerminated::new(arrayvec::ArrayVec::new()),),
+        |(version,)| Ok(version.into_inner()),
+    )
+}
+
+pub fn get_mac_address<E>() -> Request<(u8,), ([u8; 6],), types::MacAddress, E> {
+    Request::new(
+        command::Command::GetMacaddrCmd,
+        (0,),
+        ([0; 6],),
+        |(mut mac_address,)| {
+            // The firmware sends the least significant byte first
+            mac_address.reverse();
+            Ok(types::MacAddress(mac_address))
+        },
+    )
+}
+
+pub fn start_scan_networks<E>() -> Request<(), Status, (), E> {
+    Request::new(command::Command::StartScanNetworks, (), (0,), |(status,)| {
+        check_status(status, error::Error::StartScanNetworks)
+    })
+}
+
+pub fn get_scanned_networks<E>() -> Request<
+    (),
+    arrayvec::ArrayVec<[arrayvec::ArrayVec<[u8; 32]>; 16]>,
+    arrayvec::ArrayVec<[arrayvec::ArrayVec<[u8; 32]>; 16]>,
+    E,
+> {
+    Request::new(
+        command::Command::ScanNetworks,
+        (),
+        arrayvec::ArrayVec::new(),
+        Ok,
+    )
+}
+
+pub fn get_scanned_network_rssi<E>(
+    network: u8,
+) -> Request<(u8,), (param::Scalar<byteorder::LittleEndian, u32>,), i32, E> {
+    Request::new(
+        command::Command::GetIdxRssiCmd,
+        (network,),
+        (param::Scalar::le(0),),
+        |(rssi,)| Ok(rssi.into_inner() as i32),
+    )
+}
+
+pub fn get_scanned_network_encryption_type<E>(
+    network: u8,
+) -> Request<(u8,), Status, types::EncryptionType, E> {
+    use core::convert::TryFrom;
+
+    Request::new(
+        command::Command::GetIdxEnctCmd,
+        (network,),
+        (0,),
+        |(encryption_type,)| {
+            types::EncryptionType::try_from(encryption_type)
+                .map_err(error::Error::BadEncryptionType)
+        },
+    )
+}
+
+pub fn get_scanned_network_bssid<E>(network: u8) -> Request<(u8,), ([u8; 6],), [u8; 6], E> {
+    Request::new(
+        command::Command::GetIdxBssid,
+        (network,),
+        ([0; 6],),
+        |(bssid,)| Ok(bssid),
+    )
+}
+
+pub fn get_scanned_network_channel<E>(network: u8) -> Request<(u8,), (u8,), u8, E> {
+    Request::new(
+        command::Command::GetIdxChannelCmd,
+        (network,),
+        (0,),
+        |(channel,)| Ok(channel),
+    )
+}
+
+pub fn request_host_by_name<E>(
+    hostname: &str,
+) -> Request<(param::NullTerminated<&[u8]>,), Status, (), E> {
+    Request::new(
+        command::Command::ReqHostByNameCmd,
+        (param::NullTerminated::new(hostname.as_bytes()),),
+        (0,),
+        |(status,)| check_status(status, error::Error::ReqHostByName),
+    )
+}
+
+pub fn get_host_by_name<E>(
+) -> Request<(), (param::Scalar<byteorder::BigEndian, u32>,), no_std_net::Ipv4Addr, E> {
+    Request::new(
+        command::Command::GetHostByNameCmd,
+        (),
+        (param::Scalar::be(0),),
+        |(ip,)| Ok(ip.into_inner().into()),
+    )
+}
+
+pub fn get_network_data<E>() -> Request<
+    (u8,),
+    (
+        param::Scalar<byteorder::BigEndian, u32>,
+        param::Scalar<byteorder::BigEndian, u32>,
+        param::Scalar<byteorder::BigEndian, u32>,
+    ),
+    types::NetworkData,
+    E,
+> {
+    Request::new(
+        command::Command::GetIpaddrCmd,
+        (0,),
+        (
+            param::Scalar::be(0),
+            param::Scalar::be(0),
+            param::Scalar::be(0),
+        ),
+        |(ip, mask, gateway)| {
+            let ip = ip.into_inner().into();
+            let mask = mask.into_inner().into();
+            let gateway = gateway.into_inner().into();
+            Ok(types::NetworkData { ip, mask, gateway })
+        },
+    )
+}
+
+pub fn get_remote_data<E>(
+    socket: types::Socket,
+) -> Request<
+    (u8,),
+    (
+        param::Scalar<byteorder::BigEndian, u32>,
+        param::Scalar<byteorder::BigEndian, u16>,
+    ),
+    types::RemoteData,
+    E,
+> {
+    Request::new(
+        command::Command::GetRemoteDataCmd,
+        (socket.0,),
+        (param::Scalar::be(0), param::Scalar::be(0)),
+        |(ip, port)| {
+            let ip = ip.into_inner().into();
+            let port = port.into_inner();
+            Ok(types::RemoteData { ip, port })
+        },
+    )
+}
+
+pub fn join_network<'a, E>(
+    config: &types::Config<'a>,
+) -> Request<params::Network<'a>, Status, (), E> {
+    let (network, channel) = match config {
+        types::Config::Station(station_config) => (&station_config.network, None),
+        types::Config::AccessPoint(access_point_config) => (
+            &access_point_config.network,
+            Some(access_point_config.channel),
+        ),
+    };
+    let (ssid, passphrase) = match *network {
+        types::NetworkConfig::Open { ssid } => (ssid, None),
+        types::NetworkConfig::Password { ssid, password } => (ssid, Some(password)),
+    };
+    let send_params = params::Network {
+        ssid,
+        passphrase,
+        channel,
+    };
+
+    match (channel, passphrase) {
+        (None, None) => Request::new(
+            command::Command::SetNetCmd,
+            send_params,
+            (0,),
+            |(status,)| check_status(status, error::Error::SetNetwork),
+        ),
+        (None, Some(_)) => Request::new(
+            command::Command::SetPassphraseCmd,
+            send_params,
+            (0,),
+            |(status,)| check_status(status, error::Error::SetPassphrase),
+        ),
+        (Some(_), None) => Request::new(
+            command::Command::SetApNetCmd,
+            send_params,
+            (0,),
+            |(status,)| check_status(status, error::Error::SetApNetwork),
+        ),
+        (Some(_), Some(_)) => Request::new(
+            command::Command::SetApPassphraseCmd,
+            send_params,
+            (0,),
+            |(status,)| check_status(status, error::Error::SetApPassphrase),
+        ),
+    }
+}
+
+pub fn set_key<'a, E>(
+    ssid: &'a str,
+    key_idx: u8,
+    key: &'a [u8],
+) -> Request<(param::NullTerminated<&'a [u8]>, u8, &'a [u8]), Status, (), E> {
+    Request::new(
+        command::Command::SetKeyCmd,
+        (
+            param::NullTerminated::new(ssid.as_bytes()),
+            key_idx,
+            // TODO: null terminate?
+            key,
+        ),
+        (0,),
+        |(status,)| check_status(status, error::Error::SetKey),
+    )
+}
+
+pub fn set_client_cert<E>(cert: &[u8]) -> Request<(&[u8],), Status, (), E> {
+    Request::new(
+        command::Command::SetClientCertCmd,
+        (cert,),
+        (0,),
+        |(status,)| check_status(status, error::Error::SetClientCert),
+    )
+    .long_send()
+}
+
+pub fn set_cert_key<E>(key: &[u8]) -> Request<(&[u8],), Status, (), E> {
+    Request::new(command::Command::SetCertKeyCmd, (key,), (0,), |(status,)| {
+        check_status(status, error::Error::SetCertKey)
+    })
+    .long_send()
+}
+
+fn config<E>(
+    valid_params: u8,
+    local_ip: no_std_net::Ipv4Addr,
+    gateway: no_std_net::Ipv4Addr,
+    subnet: no_std_net::Ipv4Addr,
+) -> Request<
+    (
+        u8,
+        param::Scalar<byteorder::BigEndian, u32>,
+        param::Scalar<byteorder::BigEndian, u32>,
+        param::Scalar<byteorder::BigEndian, u32>,
+    ),
+    Status,
+    (),
+    E,
+> {
+    Request::new(
+        command::Command::SetIpConfigCmd,
+        (
+            valid_params,
+            param::Scalar::be(u32::from(local_ip)),
+            param::Scalar::be(u32::from(gateway)),
+            param::Scalar::be(u32::from(subnet)),
+        ),
+        (0,),
+        |(status,)| check_status(status, error::Error::SetIpConfig),
+    )
+}
+
+fn set_dns<E>(
+    valid_params: u8,
+    dns_server1: no_std_net::Ipv4Addr,
+    dns_server2: no_std_net::Ipv4Addr,
+) -> Request<
+    (
+        u8,
+        param::Scalar<byteorder::BigEndian, u32>,
+        param::Scalar<byteorder::BigEndian, u32>,
+    ),
+    Status,
+    (),
+    E,
+> {
+    Request::new(
+        command::Command::SetDnsConfigCmd,
+        (
+            valid_params,
+            param::Scalar::be(u32::from(dns_server1)),
+            param::Scalar::be(u32::from(dns_server2)),
+        ),
+        (0,),
+        |(status,)| check_status(status, error::Error::SetDnsConfig),
+    )
+}
+
+// The firmware always applies the address, gateway and mask together, and takes the DNS servers in
+// a separate command that is left out when there are none
+pub fn ip_config<E>(
+    ip_config: &types::IpConfig,
+) -> (
+    Request<
+        (
+            u8,
+            param::Scalar<byteorder::BigEndian, u32>,
+            param::Scalar<byteorder::BigEndian, u32>,
+            param::Scalar<byteorder::BigEndian, u32>,
+        ),
+        Status,
+        (),
+        E,
+    >,
+    Option<
+        Request<
+            (
+                u8,
+                param::Scalar<byteorder::BigEndian, u32>,
+                param::Scalar<byteorder::BigEndian, u32>,
+            ),
+            Status,
+            (),
+            E,
+        >,
+    >,
+) {
+    let unspecified = no_std_net::Ipv4Addr::unspecified();
+    let dns = match (ip_config.dns_server1, ip_config.dns_server2) {
+        (Some(dns_server1), Some(dns_server2)) => Some(set_dns(2, dns_server1, dns_server2)),
+        (Some(dns_server), None) | (None, Some(dns_server)) => {
+            Some(set_dns(1, dns_server, unspecified))
+        }
+        (None, None) => None,
+    };
+
+    (
+        config(3, ip_config.ip, ip_config.gateway, ip_config.mask),
+        dns,
+    )
+}
+
+pub fn set_hostname<E>(hostname: &str) -> Request<(param::NullTerminated<&[u8]>,), Status, (), E> {
+    Request::new(
+        command::Command::SetHostnameCmd,
+        (param::NullTerminated::new(hostname.as_bytes()),),
+        (0,),
+        |(status,)| check_status(status, error::Error::SetHostname),
+    )
+}
+
+pub fn disconnect<E>() -> Request<(u8,), Status, (), E> {
+    Request::new(command::Command::DisconnectCmd, (0,), (0,), |(status,)| {
+        check_status(status, error::Error::Disconnect)
+    })
+}
+
+pub fn get_current_ssid<E>(
+) -> Request<(u8,), (arrayvec::ArrayVec<[u8; 32]>,), arrayvec::ArrayVec<[u8; 32]>, E> {
+    Request::new(
+        command::Command::GetCurrSsidCmd,
+        (0,),
+        (arrayvec::ArrayVec::new(),),
+        |(ssid,)| Ok(ssid),
+    )
+}
+
+pub fn get_current_bssid<E>(
+) -> Request<(u8,), (arrayvec::ArrayVec<[u8; 6]>,), arrayvec::ArrayVec<[u8; 6]>, E> {
+    Request::new(
+        command::Command::GetCurrBssidCmd,
+        (0,),
+        (arrayvec::ArrayVec::new(),),
+        |(bssid,)| Ok(bssid),
+    )
+}
+
+pub fn get_current_rssi<E>() -> Request<(u8,), (param::Scalar<byteorder::BigEndian, u32>,), i32, E>
+{
+    Request::new(
+        command::Command::GetCurrRssiCmd,
+        (0,),
+        (param::Scalar::be(0),),
+        |(rssi,)| Ok(rssi.into_inner() as i32),
+    )
+}
+
+pub fn get_current_encryption_type<E>() -> Request<(u8,), Status, types::EncryptionType, E> {
+    use core::convert::TryFrom;
+
+    Request::new(
+        command::Command::GetCurrEnctCmd,
+        (0,),
+        (0,),
+        |(encryption_type,)| {
+            types::EncryptionType::try_from(encryption_type)
+                .map_err(error::Error::BadEncryptionType)
+        },
+    )
+}
+
+pub fn start_client_by_ip<E>(
+    ip: no_std_net::Ipv4Addr,
+    port: u16,
+    socket: types::Socket,
+    protocol_mode: types::ProtocolMode,
+) -> Request<
+    (
+        param::Scalar<byteorder::BigEndian, u32>,
+        param::Scalar<byteorder::BigEndian, u16>,
+        u8,
+        u8,
+    ),
+    Status,
+    (),
+    E,
+> {
+    Request::new(
+        command::Command::StartClientTcpCmd,
+        (
+            param::Scalar::be(u32::from(ip)),
+            param::Scalar::be(port),
+            socket.0,
+            u8::from(protocol_mode),
+        ),
+        (0,),
+        |(status,)| check_status(status, error::Error::StartClientByIp),
+    )
+}
+
+pub fn start_client_by_hostname<E>(
+    hostname: &str,
+    port: u16,
+    socket: types::Socket,
+    protocol_mode: types::ProtocolMode,
+) -> Request<
+    (
+        param::NullTerminated<&[u8]>,
+        param::Scalar<byteorder::BigEndian, u32>,
+        param::Scalar<byteorder::BigEndian, u16>,
+        u8,
+        u8,
+    ),
+    Status,
+    (),
+    E,
+> {
+    // The firmware resolves the hostname itself (and uses it for SNI and certificate validation
+    // with TLS), so the IP address is left unspecified
+    Request::new(
+        command::Command::StartClientTcpCmd,
+        (
+            param::NullTerminated::new(hostname.as_bytes()),
+            param::Scalar::be(0),
+            param::Scalar::be(port),
+            socket.0,
+            u8::from(protocol_mode),
+        ),
+        (0,),
+        |(status,)| check_status(status, error::Error::StartClientByHostname),
+    )
+}
+
+pub fn start_server<E>(
+    port: u16,
+    socket: types::Socket,
+    protocol_mode: types::ProtocolMode,
+) -> Request<(param::Scalar<byteorder::BigEndian, u16>, u8, u8), Status, (), E> {
+    Request::new(
+        command::Command::StartServerTcpCmd,
+        (param::Scalar::be(port), socket.0, u8::from(protocol_mode)),
+        (0,),
+        |(status,)| check_status(status, error::Error::StartServer),
+    )
+}
+
+pub fn start_server_multicast<E>(
+    group: no_std_net::Ipv4Addr,
+    port: u16,
+    socket: types::Socket,
+) -> Request<
+    (
+        param::Scalar<byteorder::BigEndian, u32>,
+        param::Scalar<byteorder::BigEndian, u16>,
+        u8,
+        u8,
+    ),
+    Status,
+    (),
+    E,
+> {
+    Request::new(
+        command::Command::StartServerTcpCmd,
+        (
+            param::Scalar::be(u32::from(group)),
+            param::Scalar::be(port),
+            socket.0,
+            u8::from(types::ProtocolMode::UdpMulticast),
+        ),
+        (0,),
+        |(status,)| check_status(status, error::Error::StartServer),
+    )
+}
+
+pub fn get_server_state<E>(socket: types::Socket) -> Request<(u8,), Status, types::TcpState, E> {
+    use core::convert::TryFrom;
+
+    Request::new(
+        command::Command::GetStateTcpCmd,
+        (socket.0,),
+        (0,),
+        |(state,)| types::TcpState::try_from(state).map_err(error::Error::BadTcpState),
+    )
+}
+
+pub fn stop_client<E>(socket: types::Socket) -> Request<(u8,), Status, (), E> {
+    Request::new(
+        command::Command::StopClientTcpCmd,
+        (socket.0,),
+        (0,),
+        |(status,)| check_status(status, error::Error::StopClient),
+    )
+}
+
+pub fn get_client_state<E>(socket: types::Socket) -> Request<(u8,), Status, types::TcpState, E> {
+    use core::convert::TryFrom;
+
+    Request::new(
+        command::Command::GetClientStateTcpCmd,
+        (socket.0,),
+        (0,),
+        |(state,)| types::TcpState::try_from(state).map_err(error::Error::BadTcpState),
+    )
+}
+
+pub fn avail_data<E>(
+    socket: types::Socket,
+) -> Request<(u8,), (param::Scalar<byteorder::LittleEndian, u16>,), u16, E> {
+    Request::new(
+        command::Command::AvailDataTcpCmd,
+        (socket.0,),
+        (param::Scalar::le(0),),
+        |(data,)| Ok(data.into_inner()),
+    )
+}
+
+pub fn get_data_buf<E>(
+    socket: types::Socket,
+    buf: &mut [u8],
+) -> Request<(u8, param::Scalar<byteorder::LittleEndian, u16>), (&mut [u8],), usize, E> {
+    // The firmware can't be asked for more than a 16-bit length
+    let len = buf.len().min(u16::MAX as usize);
+    Request::new(
+        command::Command::GetDatabufTcpCmd,
+        (socket.0, param::Scalar::le(len as u16)),
+        (&mut buf[..len],),
+        |(buf,)| Ok(buf.len()),
+    )
+    .long_send()
+    .long_recv()
+}
+
+pub fn send_data<E>(
+    socket: types::Socket,
+    data: &[u8],
+) -> Request<(u8, &[u8]), (param::Scalar<byteorder::LittleEndian, u16>,), usize, E> {
+    Request::new(
+        command::Command::SendDataTcpCmd,
+        (socket.0, data),
+        (param::Scalar::le(0),),
+        |(len,)| Ok(len.into_inner() as usize),
+    )
+    .long_send()
+}
+
+pub fn check_data_sent<E>(socket: types::Socket) -> Request<(u8,), Status, (), E> {
+    Request::new(
+        command::Command::DataSentTcpCmd,
+        (socket.0,),
+        (0,),
+        |(status,)| check_status(status, error::Error::CheckDataSent),
+    )
+}
+
+pub fn insert_data_buf<E>(
+    socket: types::Socket,
+    data: &[u8],
+) -> Request<(u8, &[u8]), Status, (), E> {
+    Request::new(
+        command::Command::InsertDatabufCmd,
+        (socket.0, data),
+        (0,),
+        |(status,)| check_status(status, error::Error::InsertDataBuf),
+    )
+    .long_send()
+}
+
+pub fn send_udp_data<E>(socket: types::Socket) -> Request<(u8,), Status, (), E> {
+    Request::new(
+        command::Command::SendDataUdpCmd,
+        (socket.0,),
+        (0,),
+        |(status,)| check_status(status, error::Error::SendUdpData),
+    )
+}
+
+pub fn get_socket<E>() -> Request<(), (u8,), types::Socket, E> {
+    Request::new(command::Command::GetSocketCmd, (), (0,), |(socket,)| {
+        Ok(types::Socket(socket))
+    })
+}
+
+// The pins driving the red, green and blue parts of the on-board LED
+pub const LED_PINS: [u8; 3] = [25, 26, 27];
+
+pub fn pin_mode<E>(pin: u8, mode: types::PinMode) -> Request<(u8, u8), Status, (), E> {
+    Request::new(
+        command::Command::SetPinMode,
+        (pin, u8::from(mode)),
+        (0,),
+        |(status,)| check_status(status, error::Error::PinMode),
+    )
+}
+
+pub fn digital_write<E>(pin: u8, value: u8) -> Request<(u8, u8), Status, (), E> {
+    Request::new(
+        command::Command::SetDigitalWrite,
+        (pin, value),
+        (0,),
+        |(status,)| check_status(status, error::Error::DigitalWrite),
+    )
+}
+
+pub fn analog_write<E>(pin: u8, value: u8) -> Request<(u8, u8), Status, (), E> {
+    Request::new(
+        command::Command::SetAnalogWrite,
+        (pin, value),
+        (0,),
+        |(status,)| check_status(status, error::Error::AnalogWrite),
+    )
+}
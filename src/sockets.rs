@@ -0,0 +1,172 @@
+use crate::types;
+use core::sync::atomic;
+
+// Number of sockets the NINA firmware provides
+pub const MAX_SOCKETS: usize = 10;
+
+// Keeps track of the sockets handed out by a `Wifi`. The firmware only knows about sockets once
+// they are started, so it can't tell which ones are still allocated to a handle.
+#[derive(Clone, Debug, Default)]
+pub struct SocketTable {
+    sockets: [Option<types::SocketInfo>; MAX_SOCKETS],
+    // The generation of the lease each socket was handed out with, 0 for none
+    generations: [u32; MAX_SOCKETS],
+    leases: Option<&'static SocketLeases>,
+}
+
+// Where the handles of a `Wifi` record that they are still alive. Each slot holds the generation of
+// the lease of the handle its socket was handed out to, until that handle is closed or dropped.
+#[derive(Debug)]
+pub struct SocketLeases {
+    slots: [atomic::AtomicU32; MAX_SOCKETS],
+    next_generation: atomic::AtomicU32,
+}
+
+// Held by a handle to mark its socket as alive
+#[derive(Debug)]
+pub struct Lease {
+    slot: Option<&'static atomic::AtomicU32>,
+    generation: u32,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AllocateError {
+    Exhausted,
+    InUse(types::Socket),
+}
+
+impl SocketTable {
+    pub fn allocate(
+        &mut self,
+        socket: types::Socket,
+        kind: types::SocketKind,
+    ) -> Result<Lease, AllocateError> {
+        let entry = self
+            .sockets
+            .get_mut(socket.0 as usize)
+            .ok_or(AllocateError::Exhausted)?;
+        if entry.is_some() {
+            return Err(AllocateError::InUse(socket));
+        }
+
+        *entry = Some(types::SocketInfo {
+            socket,
+            kind,
+            protocol: None,
+            state: types::SocketState::Allocated,
+        });
+        let lease = self
+            .leases
+            .map_or(Lease::NONE, |leases| leases.lease(socket));
+        self.generations[socket.0 as usize] = lease.generation;
+        Ok(lease)
+    }
+
+    pub fn set_leases(&mut self, leases: &'static SocketLeases) {
+        self.leases = Some(leases);
+    }
+
+    pub fn has_leases(&self) -> bool {
+        self.leases.is_some()
+    }
+
+    // Whether the handle of a tracked socket is gone. Sockets handed out without a lease never are.
+    pub fn is_orphaned(&self, socket: types::Socket) -> bool {
+        let index = socket.0 as usize;
+        match (self.leases, self.get(socket)) {
+            (Some(leases), Some(_)) if self.generations[index] != 0 => {
+                leases.slots[index].load(atomic::Ordering::Acquire) != self.generations[index]
+            }
+            _ => false,
+        }
+    }
+
+    pub fn set_started(
+        &mut self,
+        socket: types::Socket,
+        protocol: types::ProtocolMode,
+        state: types::SocketState,
+    ) {
+        if let Some(Some(info)) = self.sockets.get_mut(socket.0 as usize) {
+            info.protocol = Some(protocol);
+            info.state = state;
+        }
+    }
+
+    pub fn get(&self, socket: types::Socket) -> Option<types::SocketInfo> {
+        self.sockets.get(socket.0 as usize).copied().flatten()
+    }
+
+    pub fn release(&mut self, socket: types::Socket) {
+        if let Some(entry) = self.sockets.get_mut(socket.0 as usize) {
+            *entry = None;
+            self.generations[socket.0 as usize] = 0;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.sockets = Default::default();
+        self.generations = Default::default();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = types::SocketInfo> + '_ {
+        self.sockets.iter().flatten().copied()
+    }
+
+    pub fn available(&self) -> usize {
+        self.sockets.iter().filter(|entry| entry.is_none()).count()
+    }
+}
+
+impl SocketLeases {
+    pub const fn new() -> Self {
+        #[allow(clippy::declare_interior_mutable_const)]
+        const FREE: atomic::AtomicU32 = atomic::AtomicU32::new(0);
+        Self {
+            slots: [FREE; MAX_SOCKETS],
+            next_generation: atomic::AtomicU32::new(1),
+        }
+    }
+
+    // Only the `Wifi` that owns the leases hands them out, so the generation needs no atomic
+    // increment, which some targets lack
+    fn lease(&'static self, socket: types::Socket) -> Lease {
+        let generation = self.next_generation.load(atomic::Ordering::Relaxed);
+        self.next_generation.store(
+            generation.checked_add(1).unwrap_or(1),
+            atomic::Ordering::Relaxed,
+        );
+
+        let slot = &self.slots[socket.0 as usize];
+        slot.store(generation, atomic::Ordering::Release);
+        Lease {
+            slot: Some(slot),
+            generation,
+        }
+    }
+}
+
+impl Default for SocketLeases {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Lease {
+    pub const NONE: Lease = Lease {
+        slot: None,
+        generation: 0,
+    };
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        // A handle that outlived a reset no longer owns the slot once its socket was handed out
+        // again
+        if let Some(slot) = self.slot {
+            if slot.load(atomic::Ordering::Acquire) == self.generation {
+                slot.store(0, atomic::Ordering::Release);
+            }
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use core::time;
+
+// Measures the time spent waiting for something. With a clock, this is the actual elapsed time;
+// without one, it is the sum of the delays that were added, which ignores the time spent talking
+// to the module.
+#[derive(Clone, Copy, Debug)]
+pub struct Timer {
+    clock: Option<fn() -> time::Duration>,
+    start: time::Duration,
+    delayed: time::Duration,
+}
+
+impl Timer {
+    pub fn start(clock: Option<fn() -> time::Duration>) -> Self {
+        let start = clock.map_or(time::Duration::from_secs(0), |now| now());
+        let delayed = time::Duration::from_secs(0);
+        Self {
+            clock,
+            start,
+            delayed,
+        }
+    }
+
+    pub fn add_delay(&mut self, delay: time::Duration) {
+        self.delayed += delay;
+    }
+
+    pub fn elapsed(&self) -> time::Duration {
+        match self.clock {
+            Some(now) => now().saturating_sub(self.start),
+            None => self.delayed,
+        }
+    }
+}
+
+// Bounds a wait that polls without delaying in between. With a clock, it expires once `timeout` has
+// elapsed; without one, after `polls` polls, as the time they take can't be measured then.
+#[derive(Clone, Copy, Debug)]
+pub struct Deadline {
+    timer: Timer,
+    timeout: time::Duration,
+    polls: u32,
+}
+
+impl Deadline {
+    pub fn start(
+        clock: Option<fn() -> time::Duration>,
+        timeout: time::Duration,
+        polls: u32,
+    ) -> Self {
+        let timer = Timer::start(clock);
+        Self {
+            timer,
+            timeout,
+            polls,
+        }
+    }
+
+    // Called after each poll that didn't find what it waited for
+    pub fn is_expired(&mut self) -> bool {
+        match self.timer.clock {
+            Some(_) => self.timer.elapsed() >= self.timeout,
+            None => {
+                self.polls = self.polls.saturating_sub(1);
+                self.polls == 0
+            }
+        }
+    }
+}
@@ -1,12 +1,38 @@
 use crate::command;
+use crate::error;
 use crate::params;
 use core::fmt;
 use core::time;
 
+#[cfg(feature = "async")]
+mod async_spi;
+#[cfg(feature = "std")]
+mod emulator;
+#[cfg(feature = "std")]
+mod mock;
+#[cfg(feature = "std")]
+mod sim;
 mod spi;
+#[cfg(feature = "embedded-hal-1")]
+mod spi_device;
+mod uart;
 
+#[cfg(feature = "async")]
+pub use async_spi::AsyncSpiTransport;
+#[cfg(feature = "std")]
+pub use emulator::{
+    EmulatedBusy, EmulatedCs, EmulatedFrame, EmulatedReset, EmulatedSpi, Emulator, EmulatorReply,
+};
+#[cfg(feature = "std")]
+pub use mock::{MockCall, MockError, MockTransport};
+#[cfg(feature = "std")]
+pub use sim::{SimError, SimTransport};
 pub use spi::SpiError;
 pub use spi::SpiTransport;
+#[cfg(feature = "embedded-hal-1")]
+pub use spi_device::{SpiBusTransport, SpiDeviceTransport};
+pub use uart::UartError;
+pub use uart::UartTransport;
 
 pub trait Transport {
     type Error;
@@ -15,6 +41,30 @@ pub trait Transport {
 
     fn delay(&mut self, duration: time::Duration) -> Result<(), Self::Error>;
 
+    // Brings the transport back to a frame boundary after a command failed with an error for which
+    // `is_desync` holds
+    fn resync(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn is_desync(_error: &Self::Error) -> bool {
+        false
+    }
+
+    // The most socket data that a single command can send or receive
+    fn max_data_len(&self) -> usize {
+        u16::MAX as usize
+    }
+
+    // Sets the clock that the transport measures its timeouts with, as set on the `Wifi`
+    fn set_clock(&mut self, _now: fn() -> time::Duration) {}
+
+    // The malformed data behind an error, which is reported as `Error::Protocol` rather than as a
+    // transport error
+    fn protocol_error(_error: &Self::Error) -> Option<error::ProtocolError> {
+        None
+    }
+
     fn handle_cmd<SP, RP>(
         &mut self,
         command: command::Command,
@@ -27,3 +77,45 @@ pub trait Transport {
         SP: params::SendParams + fmt::Debug,
         RP: params::RecvParams + fmt::Debug;
 }
+
+#[cfg(feature = "async")]
+#[allow(async_fn_in_trait)]
+pub trait AsyncTransport {
+    type Error;
+
+    async fn reset(&mut self) -> Result<(), Self::Error>;
+
+    async fn delay(&mut self, duration: time::Duration) -> Result<(), Self::Error>;
+
+    // Brings the transport back to a frame boundary after a command failed with an error for which
+    // `is_desync` holds
+    async fn resync(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn is_desync(_error: &Self::Error) -> bool {
+        false
+    }
+
+    fn max_data_len(&self) -> usize {
+        u16::MAX as usize
+    }
+
+    fn set_clock(&mut self, _now: fn() -> time::Duration) {}
+
+    fn protocol_error(_error: &Self::Error) -> Option<error::ProtocolError> {
+        None
+    }
+
+    async fn handle_cmd<SP, RP>(
+        &mut self,
+        command: command::Command,
+        send_params: &SP,
+        recv_params: &mut RP,
+        long_send: bool,
+        long_recv: bool,
+    ) -> Result<(), Self::Error>
+    where
+        SP: params::SendParams + fmt::Debug,
+        RP: params::RecvParams + fmt::Debug;
+}
@@ -0,0 +1,224 @@
+use super::SpiError;
+use crate::command;
+use crate::error;
+use crate::frame;
+use crate::params;
+use crate::timer;
+use crate::types;
+use core::convert;
+use core::fmt;
+use core::future::Future as _;
+use core::pin;
+use core::task;
+use core::time;
+use embedded_hal_1::digital::OutputPin;
+use embedded_hal_async::delay::DelayNs;
+use embedded_hal_async::digital::Wait;
+use embedded_hal_async::spi::{Operation, SpiDevice};
+
+// Whole frames are transferred in one go, since the chip select line is owned by the `SpiDevice`
+// and must stay asserted for the entire command or reply. As the module can't be seen to
+// acknowledge the chip select then, each transfer waits `ACK_DELAY_NS` after asserting it instead.
+#[derive(Debug)]
+pub struct AsyncSpiTransport<
+    SPI,
+    BUSY,
+    RESET,
+    DELAY,
+    const N: usize = { frame::DEFAULT_FRAME_CAPACITY },
+> {
+    spi: SPI,
+    busy: BUSY,
+    reset: RESET,
+    delay: DELAY,
+    buffer: [u8; N],
+    timeouts: types::SpiTimeouts,
+    clock: Option<fn() -> time::Duration>,
+}
+
+// How long the BUSY pin is waited on at a time, between checks of the timeout
+const READY_SLICE: time::Duration = time::Duration::from_millis(1);
+
+impl<SPI, BUSY, RESET, DELAY, const N: usize> super::AsyncTransport
+    for AsyncSpiTransport<SPI, BUSY, RESET, DELAY, N>
+where
+    SPI: SpiDevice,
+    BUSY: Wait,
+    RESET: OutputPin,
+    DELAY: DelayNs,
+{
+    type Error = SpiError<SPI::Error, BUSY::Error, RESET::Error, convert::Infallible>;
+
+    #[inline]
+    async fn reset(&mut self) -> Result<(), Self::Error> {
+        #[cfg(feature = "reset-high")]
+        self.reset.set_high().map_err(SpiError::Reset)?;
+        #[cfg(not(feature = "reset-high"))]
+        self.reset.set_low().map_err(SpiError::Reset)?;
+
+        self.delay(time::Duration::from_millis(10)).await?;
+
+        #[cfg(feature = "reset-high")]
+        self.reset.set_low().map_err(SpiError::Reset)?;
+        #[cfg(not(feature = "reset-high"))]
+        self.reset.set_high().map_err(SpiError::Reset)?;
+
+        self.delay(time::Duration::from_millis(750)).await?;
+
+        Ok(())
+    }
+
+    #[inline]
+    async fn delay(&mut self, duration: time::Duration) -> Result<(), Self::Error> {
+        use core::convert::TryFrom;
+
+        let us = u32::try_from(duration.as_micros()).unwrap_or(u32::MAX);
+        self.delay.delay_us(us).await;
+        Ok(())
+    }
+
+    async fn resync(&mut self) -> Result<(), Self::Error> {
+        // Clock out a whole frame, so that a reply that came too late for the previous command
+        // isn't taken for the reply to the next one
+        self.buffer.fill(0);
+        self.await_ready().await?;
+        transfer(&mut self.spi, Operation::TransferInPlace(&mut self.buffer)).await
+    }
+
+    fn is_desync(error: &Self::Error) -> bool {
+        error.is_desync()
+    }
+
+    fn max_data_len(&self) -> usize {
+        frame::max_data_len(N)
+    }
+
+    fn set_clock(&mut self, now: fn() -> time::Duration) {
+        self.clock = Some(now);
+    }
+
+    fn protocol_error(error: &Self::Error) -> Option<error::ProtocolError> {
+        error.protocol_error()
+    }
+
+    #[inline]
+    async fn handle_cmd<SP, RP>(
+        &mut self,
+        command: command::Command,
+        send_params: &SP,
+        recv_params: &mut RP,
+        long_send: bool,
+        long_recv: bool,
+    ) -> Result<(), Self::Error>
+    where
+        SP: params::SendParams + fmt::Debug,
+        RP: params::RecvParams + fmt::Debug,
+    {
+        // Once the command is sent, the firmware commits to a reply that would be lost if it didn't fit
+        let reply_len = frame::reply_capacity(recv_params, long_recv);
+        if reply_len > N {
+            return Err(SpiError::BufferOverflow);
+        }
+
+        let len = frame::encode_cmd(&mut self.buffer, command, send_params, long_send)?;
+        self.await_ready().await?;
+        transfer(&mut self.spi, Operation::Write(&self.buffer[..len])).await?;
+        log::debug!("send {:?} {:?}", command, send_params);
+
+        self.buffer[..reply_len].fill(0);
+        self.await_ready().await?;
+        transfer(
+            &mut self.spi,
+            Operation::TransferInPlace(&mut self.buffer[..reply_len]),
+        )
+        .await?;
+        frame::decode_reply(&self.buffer[..reply_len], command, recv_params, long_recv)?;
+        log::debug!("recv {:?} {:?} -> {:?}", command, send_params, recv_params);
+
+        Ok(())
+    }
+}
+
+impl<SPI, BUSY, RESET, DELAY, const N: usize> AsyncSpiTransport<SPI, BUSY, RESET, DELAY, N>
+where
+    SPI: SpiDevice,
+    BUSY: Wait,
+    RESET: OutputPin,
+    DELAY: DelayNs,
+{
+    // The chip select is acknowledged by waiting rather than watching the BUSY pin, see above
+    #[inline]
+    pub async fn start(
+        spi: SPI,
+        busy: BUSY,
+        reset: RESET,
+        delay: DELAY,
+    ) -> Result<Self, SpiError<SPI::Error, BUSY::Error, RESET::Error, convert::Infallible>> {
+        let buffer = [0; N];
+        let mut this = Self {
+            spi,
+            busy,
+            reset,
+            delay,
+            buffer,
+            timeouts: types::SpiTimeouts::default(),
+            clock: None,
+        };
+
+        super::AsyncTransport::reset(&mut this).await?;
+
+        Ok(this)
+    }
+
+    pub fn set_timeouts(&mut self, timeouts: types::SpiTimeouts) {
+        self.timeouts = timeouts;
+    }
+
+    // Waits for the BUSY pin to go low in slices, so that the timeout is measured with the clock
+    // when there is one
+    #[inline]
+    async fn await_ready(
+        &mut self,
+    ) -> Result<(), SpiError<SPI::Error, BUSY::Error, RESET::Error, convert::Infallible>> {
+        let slice = READY_SLICE.as_micros() as u32;
+        let mut timer = timer::Timer::start(self.clock);
+        loop {
+            let mut ready = pin::pin!(self.busy.wait_for_low());
+            let mut delay = pin::pin!(self.delay.delay_us(slice));
+            let result = core::future::poll_fn(|cx| {
+                if let task::Poll::Ready(result) = ready.as_mut().poll(cx) {
+                    return task::Poll::Ready(Some(result));
+                }
+                if delay.as_mut().poll(cx).is_ready() {
+                    return task::Poll::Ready(None);
+                }
+                task::Poll::Pending
+            })
+            .await;
+            if let Some(result) = result {
+                return result.map_err(SpiError::Busy);
+            }
+
+            timer.add_delay(READY_SLICE);
+            if timer.elapsed() >= self.timeouts.ready {
+                return Err(SpiError::BusyTimeout);
+            }
+        }
+    }
+}
+
+// Runs `operation` in a transaction that first gives the module time to acknowledge the chip select
+async fn transfer<SPI, BUSY, RESET>(
+    spi: &mut SPI,
+    operation: Operation<'_, u8>,
+) -> Result<(), SpiError<SPI::Error, BUSY, RESET, convert::Infallible>>
+where
+    SPI: SpiDevice,
+{
+    spi.transaction(&mut [
+        Operation::DelayNs(super::spi_device::ACK_DELAY_NS),
+        operation,
+    ])
+    .await
+    .map_err(SpiError::Spi)
+}
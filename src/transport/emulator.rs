@@ -0,0 +1,458 @@
+use crate::command;
+use crate::encoding;
+use crate::frame;
+use crate::frame::{END_CMD, ERR_CMD, REPLY_FLAG, START_CMD};
+use core::cell;
+use core::convert;
+use core::convert::TryFrom as _;
+use core::fmt;
+use std::boxed::Box;
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::vec;
+use std::vec::Vec;
+
+// A software stand-in for the NINA SPI slave. The handles it hands out share its state and can be
+// given to `SpiTransport` in place of the real SPI bus, BUSY, CS and RESET pins; every frame the
+// transport clocks out is parsed like the firmware would and answered through a responder.
+#[derive(Clone)]
+pub struct Emulator {
+    state: Rc<cell::RefCell<State>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct EmulatedSpi {
+    state: Rc<cell::RefCell<State>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct EmulatedBusy {
+    state: Rc<cell::RefCell<State>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct EmulatedCs {
+    state: Rc<cell::RefCell<State>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct EmulatedReset {
+    state: Rc<cell::RefCell<State>>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum EmulatorReply {
+    // A well-formed reply carrying these params
+    Params(Vec<Vec<u8>>),
+    // An ERR_CMD reply, as sent by the firmware for commands it rejects
+    Error,
+    // No reply at all; the bus reads as idle
+    Silent,
+    // These exact bytes, for replies the firmware would never produce
+    Raw(Vec<u8>),
+}
+
+// A command frame as received by the emulator
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EmulatedFrame {
+    pub bytes: Vec<u8>,
+}
+
+struct State {
+    responder: Box<dyn FnMut(command::Command, &[&[u8]]) -> EmulatorReply>,
+    phase: Phase,
+    received: Vec<u8>,
+    outgoing: VecDeque<u8>,
+    pending_reply: Option<Vec<u8>>,
+    last_read: u8,
+    busy_stuck: bool,
+    in_reset: bool,
+    resets: usize,
+    frames: Vec<EmulatedFrame>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Phase {
+    Deselected,
+    Command,
+    Reply,
+}
+
+impl Emulator {
+    pub fn new(
+        responder: impl FnMut(command::Command, &[&[u8]]) -> EmulatorReply + 'static,
+    ) -> Self {
+        let state = State {
+            responder: Box::new(responder),
+            phase: Phase::Deselected,
+            received: Vec::new(),
+            outgoing: VecDeque::new(),
+            pending_reply: None,
+            last_read: 0xff,
+            busy_stuck: false,
+            in_reset: false,
+            resets: 0,
+            frames: Vec::new(),
+        };
+        let state = Rc::new(cell::RefCell::new(state));
+        Self { state }
+    }
+
+    pub fn spi(&self) -> EmulatedSpi {
+        let state = self.state.clone();
+        EmulatedSpi { state }
+    }
+
+    pub fn busy(&self) -> EmulatedBusy {
+        let state = self.state.clone();
+        EmulatedBusy { state }
+    }
+
+    pub fn cs(&self) -> EmulatedCs {
+        let state = self.state.clone();
+        EmulatedCs { state }
+    }
+
+    pub fn reset(&self) -> EmulatedReset {
+        let state = self.state.clone();
+        EmulatedReset { state }
+    }
+
+    // Holds BUSY high regardless of CS, like a module that has hung
+    pub fn set_busy_stuck(&self, stuck: bool) {
+        self.state.borrow_mut().busy_stuck = stuck;
+    }
+
+    pub fn frames(&self) -> Vec<EmulatedFrame> {
+        self.state.borrow().frames.clone()
+    }
+
+    pub fn resets(&self) -> usize {
+        self.state.borrow().resets
+    }
+}
+
+impl fmt::Debug for Emulator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Emulator")
+            .field("state", &self.state)
+            .finish()
+    }
+}
+
+impl EmulatedFrame {
+    // The command, if the frame was well-formed and named a known command
+    pub fn command(&self) -> Option<command::Command> {
+        parse_frame(&self.bytes).map(|(command, _)| command)
+    }
+
+    pub fn params(&self) -> Vec<&[u8]> {
+        parse_frame(&self.bytes)
+            .map(|(_, params)| params)
+            .unwrap_or_default()
+    }
+}
+
+impl State {
+    fn select(&mut self) {
+        self.received.clear();
+        self.outgoing.clear();
+        self.phase = match self.pending_reply.take() {
+            Some(reply) => {
+                self.outgoing.extend(reply);
+                Phase::Reply
+            }
+            None => Phase::Command,
+        };
+    }
+
+    fn deselect(&mut self) {
+        // Like the firmware, anything clocked in while a reply is being read out is dropped, as are
+        // transactions that never started a frame
+        if self.phase == Phase::Command && self.received.first() == Some(&START_CMD) {
+            let bytes = core::mem::take(&mut self.received);
+            self.pending_reply = self.respond(&bytes);
+            self.frames.push(EmulatedFrame { bytes });
+        }
+        self.phase = Phase::Deselected;
+    }
+
+    fn respond(&mut self, bytes: &[u8]) -> Option<Vec<u8>> {
+        let (command, params) = match parse_frame(bytes) {
+            Some(frame) => frame,
+            None => return Some(error_reply()),
+        };
+
+        match (self.responder)(command, &params) {
+            EmulatorReply::Params(params) => {
+                let mut reply = vec![START_CMD, u8::from(command) | REPLY_FLAG];
+                let long = command == command::Command::GetDatabufTcpCmd;
+                if frame::encode_params(&mut encoding::VecSink(&mut reply), &params, long).is_err()
+                {
+                    return Some(error_reply());
+                }
+                reply.push(END_CMD);
+                Some(reply)
+            }
+            EmulatorReply::Error => Some(error_reply()),
+            EmulatorReply::Silent => None,
+            EmulatorReply::Raw(bytes) => Some(bytes),
+        }
+    }
+
+    fn busy(&self) -> bool {
+        self.busy_stuck || self.in_reset || self.phase != Phase::Deselected
+    }
+}
+
+impl fmt::Debug for State {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("State")
+            .field("phase", &self.phase)
+            .field("received", &self.received)
+            .field("outgoing", &self.outgoing)
+            .field("pending_reply", &self.pending_reply)
+            .field("busy_stuck", &self.busy_stuck)
+            .field("in_reset", &self.in_reset)
+            .field("resets", &self.resets)
+            .field("frames", &self.frames)
+            .finish()
+    }
+}
+
+impl embedded_hal::spi::FullDuplex<u8> for EmulatedSpi {
+    type Error = convert::Infallible;
+
+    fn read(&mut self) -> nb::Result<u8, Self::Error> {
+        Ok(self.state.borrow().last_read)
+    }
+
+    fn send(&mut self, byte: u8) -> nb::Result<(), Self::Error> {
+        let mut state = self.state.borrow_mut();
+        // With CS high the module is not listening and MISO floats high
+        state.last_read = match state.phase {
+            Phase::Deselected => 0xff,
+            Phase::Command => {
+                state.received.push(byte);
+                0xff
+            }
+            Phase::Reply => state.outgoing.pop_front().unwrap_or(0xff),
+        };
+        Ok(())
+    }
+}
+
+impl embedded_hal::digital::v2::InputPin for EmulatedBusy {
+    type Error = convert::Infallible;
+
+    fn is_high(&self) -> Result<bool, Self::Error> {
+        Ok(self.state.borrow().busy())
+    }
+
+    fn is_low(&self) -> Result<bool, Self::Error> {
+        Ok(!self.state.borrow().busy())
+    }
+}
+
+impl embedded_hal::digital::v2::OutputPin for EmulatedCs {
+    type Error = convert::Infallible;
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        let mut state = self.state.borrow_mut();
+        if state.phase == Phase::Deselected && !state.in_reset {
+            state.select();
+        }
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        let mut state = self.state.borrow_mut();
+        if state.phase != Phase::Deselected {
+            state.deselect();
+        }
+        Ok(())
+    }
+}
+
+impl embedded_hal::digital::v2::OutputPin for EmulatedReset {
+    type Error = convert::Infallible;
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        #[cfg(not(feature = "reset-high"))]
+        self.state.borrow_mut().enter_reset();
+        #[cfg(feature = "reset-high")]
+        self.state.borrow_mut().leave_reset();
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        #[cfg(not(feature = "reset-high"))]
+        self.state.borrow_mut().leave_reset();
+        #[cfg(feature = "reset-high")]
+        self.state.borrow_mut().enter_reset();
+        Ok(())
+    }
+}
+
+impl State {
+    fn enter_reset(&mut self) {
+        if !self.in_reset {
+            self.in_reset = true;
+            self.resets += 1;
+        }
+        self.phase = Phase::Deselected;
+        self.received.clear();
+        self.outgoing.clear();
+        self.pending_reply = None;
+    }
+
+    fn leave_reset(&mut self) {
+        self.in_reset = false;
+    }
+}
+
+// Splits a command frame into its command and params, checking the framing and 4 byte padding
+fn parse_frame(bytes: &[u8]) -> Option<(command::Command, Vec<&[u8]>)> {
+    if bytes.first() != Some(&START_CMD) || 0 != bytes.len() % 4 {
+        return None;
+    }
+    let &command = bytes.get(1)?;
+    if command & REPLY_FLAG != 0 {
+        return None;
+    }
+    let command = command::Command::try_from(command).ok()?;
+
+    // Commands with the DATA_FLAG carry 16 bit param lengths
+    let long = (0x40..0x50).contains(&u8::from(command));
+    let mut raw_params = frame::RawParams::new(&bytes[2..], long).ok()?;
+    let params = raw_params.by_ref().collect::<Result<Vec<_>, _>>().ok()?;
+
+    match raw_params.rest().split_first() {
+        Some((&END_CMD, padding)) if padding.iter().all(|&byte| byte == 0xff) => {
+            Some((command, params))
+        }
+        _ => None,
+    }
+}
+
+fn error_reply() -> Vec<u8> {
+    vec![ERR_CMD, 0x00, END_CMD]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EmulatedBusy, EmulatedCs, EmulatedReset, EmulatedSpi, Emulator, EmulatorReply};
+    use crate::command::Command;
+    use crate::error::Error;
+    use crate::frame::{END_CMD, REPLY_FLAG, START_CMD};
+    use crate::transport::{SpiError, SpiTransport};
+    use crate::types::SpiTimeouts;
+    use core::cell::Cell;
+    use core::time;
+    use std::vec;
+
+    type EmulatedWifi = crate::Wifi<
+        SpiTransport<EmulatedSpi, EmulatedBusy, EmulatedReset, EmulatedCs, fn(time::Duration)>,
+    >;
+
+    fn wifi(emulator: &Emulator) -> EmulatedWifi {
+        let transport = SpiTransport::start(
+            emulator.spi(),
+            emulator.busy(),
+            emulator.reset(),
+            emulator.cs(),
+            (|_| {}) as fn(time::Duration),
+        )
+        .unwrap();
+        crate::Wifi::new(transport)
+    }
+
+    #[test]
+    fn handle_cmd() {
+        let emulator = Emulator::new(|command, params| match (command, params) {
+            (Command::GetFwVersionCmd, [[0]]) => EmulatorReply::Params(vec![b"1.7.4\0".to_vec()]),
+            _ => EmulatorReply::Error,
+        });
+        let mut wifi = wifi(&emulator);
+
+        assert_eq!(&wifi.get_firmware_version().unwrap()[..], b"1.7.4");
+
+        let frames = emulator.frames();
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].command(), Some(Command::GetFwVersionCmd));
+        // START_CMD, command, param count, length, param, END_CMD and padding to 8 bytes
+        assert_eq!(frames[0].bytes.len(), 8);
+    }
+
+    #[test]
+    fn await_start_cmd_timeout() {
+        let emulator = Emulator::new(|_, _| EmulatorReply::Silent);
+        let mut wifi = wifi(&emulator);
+
+        assert_eq!(wifi.rssi(), Err(Error::Transport(SpiError::Timeout)));
+    }
+
+    #[test]
+    fn error_response() {
+        let emulator = Emulator::new(|_, _| EmulatorReply::Error);
+        let mut wifi = wifi(&emulator);
+
+        assert_eq!(wifi.rssi(), Err(Error::Transport(SpiError::ErrorResponse)));
+    }
+
+    #[test]
+    fn unexpected_reply_byte() {
+        let emulator = Emulator::new(|command, _| {
+            // A reply to another command
+            let command = u8::from(command) + 1;
+            EmulatorReply::Raw(vec![START_CMD, command | REPLY_FLAG, 1, 1, 3, END_CMD])
+        });
+        let mut wifi = wifi(&emulator);
+        let command = u8::from(Command::GetCurrRssiCmd) + 1;
+
+        assert_eq!(
+            wifi.rssi(),
+            Err(Error::Transport(SpiError::UnexpectedReplyByte(
+                command | REPLY_FLAG
+            )))
+        );
+    }
+
+    #[test]
+    fn busy_timeout() {
+        let emulator = Emulator::new(|_, _| EmulatorReply::Silent);
+        let mut wifi = wifi(&emulator);
+        wifi.transport_mut().set_timeouts(SpiTimeouts {
+            ready_polls: 50,
+            ..SpiTimeouts::default()
+        });
+        emulator.set_busy_stuck(true);
+
+        assert_eq!(wifi.rssi(), Err(Error::Transport(SpiError::BusyTimeout)));
+    }
+
+    #[test]
+    fn timeouts_use_clock() {
+        std::thread_local!(static READS: Cell<u64> = const { Cell::new(0) });
+
+        // Advances by a second each time it is read, while the delays take no time at all
+        fn clock() -> time::Duration {
+            READS.with(|reads| {
+                reads.set(reads.get() + 1);
+                time::Duration::from_secs(reads.get())
+            })
+        }
+
+        let emulator = Emulator::new(|_, _| EmulatorReply::Silent);
+        let mut wifi = wifi(&emulator);
+        wifi.transport_mut().set_timeouts(SpiTimeouts {
+            reply: time::Duration::from_secs(10),
+            ..SpiTimeouts::default()
+        });
+        wifi.set_clock(clock);
+
+        assert_eq!(wifi.rssi(), Err(Error::Transport(SpiError::Timeout)));
+        // Polling stops once the clock passes the timeout rather than after the polls without one
+        assert!(READS.with(Cell::get) < 100);
+    }
+}
@@ -0,0 +1,261 @@
+use crate::command;
+use crate::encoding;
+use crate::error;
+use crate::frame;
+use crate::params;
+use core::fmt;
+use core::time;
+use std::collections::VecDeque;
+use std::vec::Vec;
+
+// A transport that records the commands it is asked to perform and answers them with scripted
+// replies, for testing code built on `Wifi` without hardware.
+#[derive(Clone, Debug, Default)]
+pub struct MockTransport {
+    replies: VecDeque<(command::Command, Result<Vec<Vec<u8>>, MockError>)>,
+    calls: Vec<MockCall>,
+    resets: usize,
+    resyncs: usize,
+    delayed: time::Duration,
+    max_data_len: Option<usize>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MockCall {
+    pub command: command::Command,
+    // The params as sent on the wire: their count followed by each length-delimited param
+    pub send_params: Vec<u8>,
+    pub long_send: bool,
+    pub long_recv: bool,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MockError {
+    Unscripted(command::Command),
+    UnexpectedCommand {
+        expected: command::Command,
+        actual: command::Command,
+    },
+    BadReply(command::Command),
+    Protocol(error::ProtocolError),
+    Injected,
+    // An injected failure after which the transport has to resync
+    Desync,
+}
+
+impl MockTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn expect(&mut self, command: command::Command, reply_params: &[&[u8]]) -> &mut Self {
+        let reply_params = reply_params.iter().map(|param| param.to_vec()).collect();
+        self.replies.push_back((command, Ok(reply_params)));
+        self
+    }
+
+    pub fn expect_error(&mut self, command: command::Command, error: MockError) -> &mut Self {
+        self.replies.push_back((command, Err(error)));
+        self
+    }
+
+    // Limits the data carried by a single command, like a transport with a fixed-size frame buffer
+    pub fn set_max_data_len(&mut self, max_data_len: usize) -> &mut Self {
+        self.max_data_len = Some(max_data_len);
+        self
+    }
+
+    pub fn calls(&self) -> &[MockCall] {
+        &self.calls
+    }
+
+    pub fn pending(&self) -> usize {
+        self.replies.len()
+    }
+
+    pub fn resets(&self) -> usize {
+        self.resets
+    }
+
+    pub fn resyncs(&self) -> usize {
+        self.resyncs
+    }
+
+    pub fn delayed(&self) -> time::Duration {
+        self.delayed
+    }
+}
+
+impl MockCall {
+    pub fn params(&self) -> Vec<&[u8]> {
+        frame::RawParams::new(&self.send_params, self.long_send)
+            .into_iter()
+            .flatten()
+            .map_while(Result::ok)
+            .collect()
+    }
+}
+
+impl super::Transport for MockTransport {
+    type Error = MockError;
+
+    fn reset(&mut self) -> Result<(), Self::Error> {
+        self.resets += 1;
+        Ok(())
+    }
+
+    fn delay(&mut self, duration: time::Duration) -> Result<(), Self::Error> {
+        self.delayed += duration;
+        Ok(())
+    }
+
+    fn resync(&mut self) -> Result<(), Self::Error> {
+        self.resyncs += 1;
+        Ok(())
+    }
+
+    fn is_desync(error: &Self::Error) -> bool {
+        *error == MockError::Desync
+    }
+
+    fn max_data_len(&self) -> usize {
+        self.max_data_len.unwrap_or(u16::MAX as usize)
+    }
+
+    fn protocol_error(error: &Self::Error) -> Option<error::ProtocolError> {
+        match *error {
+            MockError::Protocol(error) => Some(error),
+            _ => None,
+        }
+    }
+
+    fn handle_cmd<SP, RP>(
+        &mut self,
+        command: command::Command,
+        send_params: &SP,
+        recv_params: &mut RP,
+        long_send: bool,
+        long_recv: bool,
+    ) -> Result<(), Self::Error>
+    where
+        SP: params::SendParams + fmt::Debug,
+        RP: params::RecvParams + fmt::Debug,
+    {
+        let mut encoded_send_params = Vec::new();
+        send_params
+            .send(&mut encoding::VecSink(&mut encoded_send_params), long_send)
+            .map_err(MockError::Protocol)?;
+        self.calls.push(MockCall {
+            command,
+            send_params: encoded_send_params,
+            long_send,
+            long_recv,
+        });
+
+        let (expected, reply_params) = self
+            .replies
+            .pop_front()
+            .ok_or(MockError::Unscripted(command))?;
+        if expected != command {
+            return Err(MockError::UnexpectedCommand {
+                expected,
+                actual: command,
+            });
+        }
+        let reply_params = reply_params?;
+
+        let mut reply = Vec::new();
+        frame::encode_params(&mut encoding::VecSink(&mut reply), &reply_params, long_recv)
+            .map_err(|_| MockError::BadReply(command))?;
+        recv_params
+            .recv(&mut frame::Reader::new(&reply), long_recv)
+            .map_err(|error| match error {
+                frame::FrameError::Protocol(error) => MockError::Protocol(error),
+                _ => MockError::BadReply(command),
+            })?;
+        log::debug!("mock {:?} {:?} -> {:?}", command, send_params, recv_params);
+
+        Ok(())
+    }
+}
+
+// Answers async commands from the same script, completing every call immediately
+#[cfg(feature = "async")]
+impl super::AsyncTransport for MockTransport {
+    type Error = MockError;
+
+    async fn reset(&mut self) -> Result<(), Self::Error> {
+        super::Transport::reset(self)
+    }
+
+    async fn delay(&mut self, duration: time::Duration) -> Result<(), Self::Error> {
+        super::Transport::delay(self, duration)
+    }
+
+    async fn resync(&mut self) -> Result<(), Self::Error> {
+        super::Transport::resync(self)
+    }
+
+    fn is_desync(error: &Self::Error) -> bool {
+        <Self as super::Transport>::is_desync(error)
+    }
+
+    fn max_data_len(&self) -> usize {
+        super::Transport::max_data_len(self)
+    }
+
+    fn protocol_error(error: &Self::Error) -> Option<error::ProtocolError> {
+        <Self as super::Transport>::protocol_error(error)
+    }
+
+    async fn handle_cmd<SP, RP>(
+        &mut self,
+        command: command::Command,
+        send_params: &SP,
+        recv_params: &mut RP,
+        long_send: bool,
+        long_recv: bool,
+    ) -> Result<(), Self::Error>
+    where
+        SP: params::SendParams + fmt::Debug,
+        RP: params::RecvParams + fmt::Debug,
+    {
+        super::Transport::handle_cmd(
+            self,
+            command,
+            send_params,
+            recv_params,
+            long_send,
+            long_recv,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MockError, MockTransport};
+    use crate::command::Command;
+    use crate::error::Error;
+
+    #[test]
+    fn unexpected_command() {
+        let mut mock = MockTransport::new();
+        mock.expect(Command::GetConnStatusCmd, &[&[3]]);
+        let mut wifi = crate::Wifi::new(mock);
+
+        assert_eq!(
+            wifi.rssi(),
+            Err(Error::Transport(MockError::UnexpectedCommand {
+                expected: Command::GetConnStatusCmd,
+                actual: Command::GetCurrRssiCmd,
+            }))
+        );
+        assert_eq!(
+            wifi.rssi(),
+            Err(Error::Transport(MockError::Unscripted(
+                Command::GetCurrRssiCmd
+            )))
+        );
+        assert_eq!(wifi.transport().calls().len(), 2);
+    }
+}
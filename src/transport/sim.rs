@@ -0,0 +1,527 @@
+use crate::command;
+use crate::encoding;
+use crate::frame;
+use crate::params;
+use crate::types;
+use core::convert::TryInto as _;
+use core::fmt;
+use core::time;
+use std::collections::HashMap;
+use std::io;
+use std::io::{Read as _, Write as _};
+use std::net;
+use std::string::String;
+use std::thread;
+use std::vec;
+use std::vec::Vec;
+
+// A transport backed by a fake firmware that serves the socket commands with real `std::net`
+// sockets on the host, so that code built on `Wifi` can talk to local test servers. It reports
+// being connected to a network until told to disconnect, with the loopback address as its own.
+#[derive(Debug)]
+pub struct SimTransport {
+    sockets: Vec<Option<SimSocket>>,
+    hosts: HashMap<String, net::Ipv4Addr>,
+    resolved: Option<net::Ipv4Addr>,
+    connected: bool,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SimError {
+    BadParams(command::Command),
+    BadReply(command::Command),
+    Unsupported(command::Command),
+}
+
+#[derive(Debug)]
+enum SimSocket {
+    TcpClient(net::TcpStream),
+    TcpServer(net::TcpListener),
+    Udp(SimUdpSocket),
+}
+
+#[derive(Debug)]
+struct SimUdpSocket {
+    socket: net::UdpSocket,
+    destination: Option<net::SocketAddr>,
+    outgoing: Vec<u8>,
+    incoming: Vec<u8>,
+    remote: Option<net::SocketAddr>,
+}
+
+const SOCKETS: usize = 10;
+const NO_SOCKET: u8 = 255;
+const CONNECT_TIMEOUT: time::Duration = time::Duration::from_secs(5);
+const MAX_DATAGRAM_LEN: usize = 65535;
+
+impl SimTransport {
+    pub fn new() -> Self {
+        let sockets = (0..SOCKETS).map(|_| None).collect();
+        let hosts = HashMap::new();
+        let resolved = None;
+        let connected = true;
+        Self {
+            sockets,
+            hosts,
+            resolved,
+            connected,
+        }
+    }
+
+    // Makes `hostname` resolve to `ip`, without going through the host's resolver
+    pub fn add_host(&mut self, hostname: &str, ip: net::Ipv4Addr) -> &mut Self {
+        self.hosts.insert(hostname.into(), ip);
+        self
+    }
+
+    fn respond(
+        &mut self,
+        command: command::Command,
+        params: &[&[u8]],
+    ) -> Result<Vec<Vec<u8>>, SimError> {
+        use command::Command;
+
+        let bad_params = || SimError::BadParams(command);
+        let status = |ok: bool| Ok(vec![vec![ok as u8]]);
+
+        match (command, params) {
+            (Command::GetFwVersionCmd, [_]) => Ok(vec![b"1.7.4\0".to_vec()]),
+            (Command::GetConnStatusCmd, []) => {
+                let state = if self.connected {
+                    types::ConnectionState::Connected
+                } else {
+                    types::ConnectionState::Disconnected
+                };
+                Ok(vec![vec![u8::from(state)]])
+            }
+            (Command::SetNetCmd, [_]) | (Command::SetPassphraseCmd, [_, _]) => {
+                self.connected = true;
+                status(true)
+            }
+            (Command::DisconnectCmd, [_]) => {
+                self.connected = false;
+                status(true)
+            }
+            (Command::GetIpaddrCmd, [_]) => Ok(vec![
+                net::Ipv4Addr::LOCALHOST.octets().to_vec(),
+                net::Ipv4Addr::new(255, 0, 0, 0).octets().to_vec(),
+                net::Ipv4Addr::LOCALHOST.octets().to_vec(),
+            ]),
+            (Command::ReqHostByNameCmd, [hostname]) => {
+                let hostname = string(hostname).ok_or_else(bad_params)?;
+                self.resolved = self.resolve(hostname);
+                status(self.resolved.is_some())
+            }
+            (Command::GetHostByNameCmd, []) => {
+                let ip = self.resolved.unwrap_or(net::Ipv4Addr::UNSPECIFIED);
+                Ok(vec![ip.octets().to_vec()])
+            }
+            (Command::GetSocketCmd, []) => {
+                let socket = self.sockets.iter().position(Option::is_none);
+                Ok(vec![vec![socket.map_or(NO_SOCKET, |socket| socket as u8)]])
+            }
+            (Command::StartClientTcpCmd, [ip, port, socket, mode]) => {
+                let ip = ipv4(ip).ok_or_else(bad_params)?;
+                let port = be_u16(port).ok_or_else(bad_params)?;
+                let socket = self.socket_index(socket).ok_or_else(bad_params)?;
+                status(self.start_client(socket, ip, port, mode).is_ok())
+            }
+            (Command::StartClientTcpCmd, [hostname, _, port, socket, mode]) => {
+                let hostname = string(hostname).ok_or_else(bad_params)?;
+                let port = be_u16(port).ok_or_else(bad_params)?;
+                let socket = self.socket_index(socket).ok_or_else(bad_params)?;
+                let started = match self.resolve(hostname) {
+                    Some(ip) => self.start_client(socket, ip, port, mode).is_ok(),
+                    None => false,
+                };
+                status(started)
+            }
+            (Command::StartServerTcpCmd, [port, socket, mode]) => {
+                let port = be_u16(port).ok_or_else(bad_params)?;
+                let socket = self.socket_index(socket).ok_or_else(bad_params)?;
+                status(self.start_server(socket, port, mode).is_ok())
+            }
+            (Command::StartServerTcpCmd, [group, port, socket, _]) => {
+                let group = ipv4(group).ok_or_else(bad_params)?;
+                let port = be_u16(port).ok_or_else(bad_params)?;
+                let socket = self.socket_index(socket).ok_or_else(bad_params)?;
+                status(self.start_multicast(socket, group, port).is_ok())
+            }
+            (Command::GetStateTcpCmd, [socket]) => {
+                let socket = self.socket_index(socket).ok_or_else(bad_params)?;
+                let state = match self.sockets[socket] {
+                    Some(SimSocket::TcpServer(_)) => types::TcpState::Listen,
+                    _ => types::TcpState::Closed,
+                };
+                Ok(vec![vec![u8::from(state)]])
+            }
+            (Command::GetClientStateTcpCmd, [socket]) => {
+                let socket = self.socket_index(socket).ok_or_else(bad_params)?;
+                let state = match &self.sockets[socket] {
+                    Some(SimSocket::TcpClient(stream)) if !closed(stream) => {
+                        types::TcpState::Established
+                    }
+                    _ => types::TcpState::Closed,
+                };
+                Ok(vec![vec![u8::from(state)]])
+            }
+            (Command::StopClientTcpCmd, [socket]) => {
+                let socket = self.socket_index(socket).ok_or_else(bad_params)?;
+                self.sockets[socket] = None;
+                status(true)
+            }
+            (Command::AvailDataTcpCmd, [socket]) => {
+                let socket = self.socket_index(socket).ok_or_else(bad_params)?;
+                let available = self.available(socket);
+                Ok(vec![available.to_le_bytes().to_vec()])
+            }
+            (Command::GetDatabufTcpCmd, [socket, len]) => {
+                let socket = self.socket_index(socket).ok_or_else(bad_params)?;
+                let len = le_u16(len).ok_or_else(bad_params)?;
+                Ok(vec![self.read(socket, len as usize)])
+            }
+            (Command::SendDataTcpCmd, [socket, data]) => {
+                let socket = self.socket_index(socket).ok_or_else(bad_params)?;
+                let sent = match &mut self.sockets[socket] {
+                    Some(SimSocket::TcpClient(stream)) => {
+                        write_all(stream, data).map_or(0, |_| data.len())
+                    }
+                    _ => 0,
+                };
+                Ok(vec![(sent as u16).to_le_bytes().to_vec()])
+            }
+            (Command::DataSentTcpCmd, [socket]) => {
+                let socket = self.socket_index(socket).ok_or_else(bad_params)?;
+                status(matches!(
+                    self.sockets[socket],
+                    Some(SimSocket::TcpClient(_))
+                ))
+            }
+            (Command::InsertDatabufCmd, [socket, data]) => {
+                let socket = self.socket_index(socket).ok_or_else(bad_params)?;
+                match &mut self.sockets[socket] {
+                    Some(SimSocket::Udp(udp)) => {
+                        udp.outgoing.extend_from_slice(data);
+                        status(true)
+                    }
+                    _ => status(false),
+                }
+            }
+            (Command::SendDataUdpCmd, [socket]) => {
+                let socket = self.socket_index(socket).ok_or_else(bad_params)?;
+                match &mut self.sockets[socket] {
+                    Some(SimSocket::Udp(udp)) => {
+                        let outgoing = core::mem::take(&mut udp.outgoing);
+                        let sent = udp.destination.is_some_and(|destination| {
+                            udp.socket.send_to(&outgoing, destination).is_ok()
+                        });
+                        status(sent)
+                    }
+                    _ => status(false),
+                }
+            }
+            (Command::GetRemoteDataCmd, [socket]) => {
+                let socket = self.socket_index(socket).ok_or_else(bad_params)?;
+                let remote = match &self.sockets[socket] {
+                    Some(SimSocket::TcpClient(stream)) => stream.peer_addr().ok(),
+                    Some(SimSocket::Udp(udp)) => udp.remote,
+                    _ => None,
+                };
+                let (ip, port) = match remote {
+                    Some(net::SocketAddr::V4(remote)) => (*remote.ip(), remote.port()),
+                    _ => (net::Ipv4Addr::UNSPECIFIED, 0),
+                };
+                Ok(vec![ip.octets().to_vec(), port.to_be_bytes().to_vec()])
+            }
+            (Command::GetFwVersionCmd, _)
+            | (Command::GetConnStatusCmd, _)
+            | (Command::SetNetCmd, _)
+            | (Command::SetPassphraseCmd, _)
+            | (Command::DisconnectCmd, _)
+            | (Command::GetIpaddrCmd, _)
+            | (Command::ReqHostByNameCmd, _)
+            | (Command::GetHostByNameCmd, _)
+            | (Command::GetSocketCmd, _)
+            | (Command::StartClientTcpCmd, _)
+            | (Command::StartServerTcpCmd, _)
+            | (Command::GetStateTcpCmd, _)
+            | (Command::GetClientStateTcpCmd, _)
+            | (Command::StopClientTcpCmd, _)
+            | (Command::AvailDataTcpCmd, _)
+            | (Command::GetDatabufTcpCmd, _)
+            | (Command::SendDataTcpCmd, _)
+            | (Command::DataSentTcpCmd, _)
+            | (Command::InsertDatabufCmd, _)
+            | (Command::SendDataUdpCmd, _)
+            | (Command::GetRemoteDataCmd, _) => Err(bad_params()),
+            _ => Err(SimError::Unsupported(command)),
+        }
+    }
+
+    fn socket_index(&self, socket: &[u8]) -> Option<usize> {
+        match *socket {
+            [socket] if (socket as usize) < self.sockets.len() => Some(socket as usize),
+            _ => None,
+        }
+    }
+
+    fn resolve(&self, hostname: &str) -> Option<net::Ipv4Addr> {
+        use std::net::ToSocketAddrs as _;
+
+        if let Some(&ip) = self.hosts.get(hostname) {
+            return Some(ip);
+        }
+        (hostname, 0)
+            .to_socket_addrs()
+            .ok()?
+            .find_map(|addr| match addr {
+                net::SocketAddr::V4(addr) => Some(*addr.ip()),
+                net::SocketAddr::V6(_) => None,
+            })
+    }
+
+    fn start_client(
+        &mut self,
+        socket: usize,
+        ip: net::Ipv4Addr,
+        port: u16,
+        mode: &[u8],
+    ) -> io::Result<()> {
+        let address = net::SocketAddr::from((ip, port));
+        let sim_socket = match mode_of(mode)? {
+            types::ProtocolMode::Tcp => {
+                let stream = net::TcpStream::connect_timeout(&address, CONNECT_TIMEOUT)?;
+                stream.set_nodelay(true)?;
+                SimSocket::TcpClient(stream)
+            }
+            types::ProtocolMode::Udp => match self.sockets[socket].take() {
+                // Sending from a bound socket keeps its local port
+                Some(SimSocket::Udp(mut udp)) => {
+                    udp.destination = Some(address);
+                    udp.outgoing.clear();
+                    SimSocket::Udp(udp)
+                }
+                _ => {
+                    let mut udp = SimUdpSocket::bind((net::Ipv4Addr::UNSPECIFIED, 0))?;
+                    udp.destination = Some(address);
+                    SimSocket::Udp(udp)
+                }
+            },
+            types::ProtocolMode::Tls | types::ProtocolMode::UdpMulticast => {
+                return Err(io::ErrorKind::Unsupported.into())
+            }
+        };
+        self.sockets[socket] = Some(sim_socket);
+        Ok(())
+    }
+
+    fn start_server(&mut self, socket: usize, port: u16, mode: &[u8]) -> io::Result<()> {
+        let address = (net::Ipv4Addr::LOCALHOST, port);
+        let sim_socket = match mode_of(mode)? {
+            types::ProtocolMode::Tcp => {
+                let listener = net::TcpListener::bind(address)?;
+                listener.set_nonblocking(true)?;
+                SimSocket::TcpServer(listener)
+            }
+            types::ProtocolMode::Udp => SimSocket::Udp(SimUdpSocket::bind(address)?),
+            types::ProtocolMode::Tls | types::ProtocolMode::UdpMulticast => {
+                return Err(io::ErrorKind::Unsupported.into())
+            }
+        };
+        self.sockets[socket] = Some(sim_socket);
+        Ok(())
+    }
+
+    fn start_multicast(
+        &mut self,
+        socket: usize,
+        group: net::Ipv4Addr,
+        port: u16,
+    ) -> io::Result<()> {
+        let udp = SimUdpSocket::bind((net::Ipv4Addr::UNSPECIFIED, port))?;
+        udp.socket
+            .join_multicast_v4(&group, &net::Ipv4Addr::UNSPECIFIED)?;
+        self.sockets[socket] = Some(SimSocket::Udp(udp));
+        Ok(())
+    }
+
+    // Like the firmware, reports the accepted socket for servers and the pending bytes otherwise
+    fn available(&mut self, socket: usize) -> u16 {
+        match &mut self.sockets[socket] {
+            Some(SimSocket::TcpClient(stream)) => {
+                let mut buf = [0; crate::BUFFER_CAPACITY];
+                let available = stream
+                    .set_nonblocking(true)
+                    .and_then(|()| stream.peek(&mut buf))
+                    .unwrap_or(0);
+                let _ = stream.set_nonblocking(false);
+                available as u16
+            }
+            Some(SimSocket::TcpServer(listener)) => match listener.accept() {
+                Ok((stream, _)) => {
+                    let free = self.sockets.iter().position(Option::is_none);
+                    match (free, stream.set_nonblocking(false)) {
+                        (Some(client), Ok(())) => {
+                            self.sockets[client] = Some(SimSocket::TcpClient(stream));
+                            client as u16
+                        }
+                        _ => u16::from(NO_SOCKET),
+                    }
+                }
+                Err(_) => u16::from(NO_SOCKET),
+            },
+            Some(SimSocket::Udp(udp)) => {
+                if udp.incoming.is_empty() {
+                    udp.recv();
+                }
+                udp.incoming.len().min(u16::MAX as usize) as u16
+            }
+            None => 0,
+        }
+    }
+
+    fn read(&mut self, socket: usize, len: usize) -> Vec<u8> {
+        match &mut self.sockets[socket] {
+            Some(SimSocket::TcpClient(stream)) => {
+                let mut data = vec![0; len];
+                let read = stream
+                    .set_nonblocking(true)
+                    .and_then(|()| stream.read(&mut data))
+                    .unwrap_or(0);
+                let _ = stream.set_nonblocking(false);
+                data.truncate(read);
+                data
+            }
+            Some(SimSocket::Udp(udp)) => {
+                let len = len.min(udp.incoming.len());
+                udp.incoming.drain(..len).collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl Default for SimTransport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SimUdpSocket {
+    fn bind(address: impl net::ToSocketAddrs) -> io::Result<Self> {
+        let socket = net::UdpSocket::bind(address)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket,
+            destination: None,
+            outgoing: Vec::new(),
+            incoming: Vec::new(),
+            remote: None,
+        })
+    }
+
+    fn recv(&mut self) {
+        let mut buf = vec![0; MAX_DATAGRAM_LEN];
+        if let Ok((len, remote)) = self.socket.recv_from(&mut buf) {
+            buf.truncate(len);
+            self.incoming = buf;
+            self.remote = Some(remote);
+        }
+    }
+}
+
+impl super::Transport for SimTransport {
+    type Error = SimError;
+
+    fn reset(&mut self) -> Result<(), Self::Error> {
+        self.sockets.iter_mut().for_each(|socket| *socket = None);
+        self.resolved = None;
+        Ok(())
+    }
+
+    fn delay(&mut self, duration: time::Duration) -> Result<(), Self::Error> {
+        thread::sleep(duration);
+        Ok(())
+    }
+
+    fn handle_cmd<SP, RP>(
+        &mut self,
+        command: command::Command,
+        send_params: &SP,
+        recv_params: &mut RP,
+        long_send: bool,
+        long_recv: bool,
+    ) -> Result<(), Self::Error>
+    where
+        SP: params::SendParams + fmt::Debug,
+        RP: params::RecvParams + fmt::Debug,
+    {
+        let mut encoded_send_params = Vec::new();
+        send_params
+            .send(&mut encoding::VecSink(&mut encoded_send_params), long_send)
+            .map_err(|_| SimError::BadParams(command))?;
+        let params = frame::RawParams::new(&encoded_send_params, long_send)
+            .into_iter()
+            .flatten()
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| SimError::BadParams(command))?;
+
+        let reply_params = self.respond(command, &params)?;
+
+        let mut reply = Vec::new();
+        frame::encode_params(&mut encoding::VecSink(&mut reply), &reply_params, long_recv)
+            .map_err(|_| SimError::BadReply(command))?;
+        recv_params
+            .recv(&mut frame::Reader::new(&reply), long_recv)
+            .map_err(|_| SimError::BadReply(command))?;
+        log::debug!("sim {:?} {:?} -> {:?}", command, send_params, recv_params);
+
+        Ok(())
+    }
+}
+
+fn mode_of(mode: &[u8]) -> io::Result<types::ProtocolMode> {
+    use core::convert::TryFrom as _;
+
+    match *mode {
+        [mode] => {
+            types::ProtocolMode::try_from(mode).map_err(|_| io::ErrorKind::InvalidInput.into())
+        }
+        _ => Err(io::ErrorKind::InvalidInput.into()),
+    }
+}
+
+fn closed(stream: &net::TcpStream) -> bool {
+    let closed = stream
+        .set_nonblocking(true)
+        .and_then(|()| stream.peek(&mut [0]))
+        .map_or_else(
+            |error| error.kind() != io::ErrorKind::WouldBlock,
+            |len| len == 0,
+        );
+    let _ = stream.set_nonblocking(false);
+    closed
+}
+
+fn write_all(stream: &mut net::TcpStream, data: &[u8]) -> io::Result<()> {
+    stream.write_all(data)?;
+    stream.flush()
+}
+
+fn string(param: &[u8]) -> Option<&str> {
+    let param = param.strip_suffix(&[0]).unwrap_or(param);
+    core::str::from_utf8(param).ok()
+}
+
+fn ipv4(param: &[u8]) -> Option<net::Ipv4Addr> {
+    let octets: [u8; 4] = param.try_into().ok()?;
+    Some(octets.into())
+}
+
+fn be_u16(param: &[u8]) -> Option<u16> {
+    Some(u16::from_be_bytes(param.try_into().ok()?))
+}
+
+fn le_u16(param: &[u8]) -> Option<u16> {
+    Some(u16::from_le_bytes(param.try_into().ok()?))
+}
@@ -1,6 +1,12 @@
 use crate::command;
+use crate::encoding;
+use crate::error;
+use crate::frame;
+use crate::frame::{END_CMD, ERR_CMD, REPLY_FLAG, START_CMD};
 use crate::full_duplex::FullDuplexExt as _;
 use crate::params;
+use crate::timer;
+use crate::types;
 use core::fmt;
 use core::time;
 use embedded_hal::digital::v2::{InputPin, OutputPin};
@@ -12,6 +18,8 @@ pub struct SpiTransport<SPI, BUSY, RESET, CS, DELAY> {
     reset: RESET,
     cs: CS,
     delay: DELAY,
+    timeouts: types::SpiTimeouts,
+    clock: Option<fn() -> time::Duration>,
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -23,13 +31,34 @@ pub enum SpiError<SPI, BUSY, RESET, CS> {
     Timeout,
     ErrorResponse,
     UnexpectedReplyByte(u8),
+    BufferOverflow,
+    Protocol(error::ProtocolError),
+    BusyTimeout,
 }
 
-const START_CMD: u8 = 0xe0;
-const END_CMD: u8 = 0xee;
-const ERR_CMD: u8 = 0xef;
-const REPLY_FLAG: u8 = 1 << 7;
-const WAIT_REPLY_TIMEOUT_BYTES: usize = 1000;
+// Most bytes clocked out to get past the rest of a reply
+const RESYNC_BYTES: usize = 1000;
+
+impl<SPI, BUSY, RESET, CS> From<frame::FrameError> for SpiError<SPI, BUSY, RESET, CS> {
+    fn from(error: frame::FrameError) -> Self {
+        match error {
+            frame::FrameError::Overflow => SpiError::BufferOverflow,
+            frame::FrameError::Timeout => SpiError::Timeout,
+            frame::FrameError::ErrorResponse => SpiError::ErrorResponse,
+            frame::FrameError::UnexpectedReplyByte(byte) => SpiError::UnexpectedReplyByte(byte),
+            frame::FrameError::Protocol(error) => SpiError::Protocol(error),
+        }
+    }
+}
+
+impl<SPI, BUSY, RESET, CS> From<encoding::LinkError<SPI>> for SpiError<SPI, BUSY, RESET, CS> {
+    fn from(error: encoding::LinkError<SPI>) -> Self {
+        match error {
+            encoding::LinkError::Link(error) => SpiError::Spi(error),
+            encoding::LinkError::Protocol(error) => SpiError::Protocol(error),
+        }
+    }
+}
 
 impl<SPI, BUSY, RESET, CS, DELAY> super::Transport for SpiTransport<SPI, BUSY, RESET, CS, DELAY>
 where
@@ -68,6 +97,32 @@ where
         Ok(())
     }
 
+    #[inline]
+    fn resync(&mut self) -> Result<(), Self::Error> {
+        // Clock out what is left of a late or partially read reply, so that it isn't taken for
+        // the reply to the next command
+        self.transaction(|spi| {
+            for _ in 0..RESYNC_BYTES {
+                if Self::recv_byte(spi)? == END_CMD {
+                    break;
+                }
+            }
+            Ok(())
+        })
+    }
+
+    fn is_desync(error: &Self::Error) -> bool {
+        error.is_desync()
+    }
+
+    fn set_clock(&mut self, now: fn() -> time::Duration) {
+        self.clock = Some(now);
+    }
+
+    fn protocol_error(error: &Self::Error) -> Option<error::ProtocolError> {
+        error.protocol_error()
+    }
+
     #[inline]
     fn handle_cmd<SP, RP>(
         &mut self,
@@ -84,7 +139,7 @@ where
         self.transaction(|spi| {
             Self::send_byte(spi, START_CMD)?;
             Self::send_byte(spi, u8::from(command) & !REPLY_FLAG)?;
-            send_params.send(spi, long_send).map_err(SpiError::Spi)?;
+            send_params.send(spi, long_send)?;
             Self::send_byte(spi, END_CMD)?;
 
             // Pad to 4 byte boundary
@@ -97,10 +152,12 @@ where
             log::debug!("send {:?} {:?}", command, send_params);
             Ok(())
         })?;
+        let mut deadline =
+            timer::Deadline::start(self.clock, self.timeouts.reply, self.timeouts.reply_polls);
         self.transaction(|spi| {
-            Self::await_start_cmd(spi)?;
+            Self::await_start_cmd(spi, &mut deadline)?;
             Self::expect_byte(spi, u8::from(command) | REPLY_FLAG)?;
-            recv_params.recv(spi, long_recv).map_err(SpiError::Spi)?;
+            recv_params.recv(spi, long_recv)?;
             Self::expect_byte(spi, END_CMD)?;
 
             log::debug!("recv {:?} {:?} -> {:?}", command, send_params, recv_params);
@@ -132,6 +189,8 @@ where
             reset,
             cs,
             delay,
+            timeouts: types::SpiTimeouts::default(),
+            clock: None,
         };
 
         super::Transport::reset(&mut this)?;
@@ -139,11 +198,16 @@ where
         Ok(this)
     }
 
+    pub fn set_timeouts(&mut self, timeouts: types::SpiTimeouts) {
+        self.timeouts = timeouts;
+    }
+
     #[inline]
     fn await_start_cmd(
         spi: &mut SPI,
+        deadline: &mut timer::Deadline,
     ) -> Result<(), SpiError<SPI::Error, BUSY::Error, RESET::Error, CS::Error>> {
-        for _ in 0..=WAIT_REPLY_TIMEOUT_BYTES {
+        loop {
             let byte = Self::recv_byte(spi)?;
             if byte == ERR_CMD {
                 return Err(SpiError::ErrorResponse);
@@ -151,8 +215,10 @@ where
             if byte == START_CMD {
                 return Ok(());
             }
+            if deadline.is_expired() {
+                return Err(SpiError::Timeout);
+            }
         }
-        Err(SpiError::Timeout)
     }
 
     #[inline]
@@ -191,11 +257,21 @@ where
         )
             -> Result<R, SpiError<SPI::Error, BUSY::Error, RESET::Error, CS::Error>>,
     ) -> Result<R, SpiError<SPI::Error, BUSY::Error, RESET::Error, CS::Error>> {
-        while self.busy.is_high().map_err(SpiError::Busy)? {}
+        let busy = &self.busy;
+        await_busy(
+            || busy.is_low(),
+            timer::Deadline::start(self.clock, self.timeouts.ready, self.timeouts.ready_polls),
+        )?;
 
         self.cs.set_low().map_err(SpiError::ChipSelect)?;
 
-        while self.busy.is_low().map_err(SpiError::Busy)? {}
+        if let Err(err) = await_busy(
+            || busy.is_high(),
+            timer::Deadline::start(self.clock, self.timeouts.ack, self.timeouts.ack_polls),
+        ) {
+            self.cs.set_high().map_err(SpiError::ChipSelect)?;
+            return Err(err);
+        }
 
         let result = func(&mut self.spi);
 
@@ -204,3 +280,37 @@ where
         result
     }
 }
+
+impl<SPI, BUSY, RESET, CS> SpiError<SPI, BUSY, RESET, CS> {
+    pub(super) fn protocol_error(&self) -> Option<error::ProtocolError> {
+        match *self {
+            SpiError::Protocol(error) => Some(error),
+            _ => None,
+        }
+    }
+
+    // Whether the module may still be sending the reply that the command failed on, or one that
+    // it sent too late for an earlier command
+    pub(super) fn is_desync(&self) -> bool {
+        matches!(
+            self,
+            SpiError::Timeout
+                | SpiError::ErrorResponse
+                | SpiError::UnexpectedReplyByte(_)
+                | SpiError::Protocol(_)
+        )
+    }
+}
+
+// Polls the BUSY pin until `is_done` holds, giving up once `deadline` expires
+pub(super) fn await_busy<SPI, BUSY, RESET, CS>(
+    mut is_done: impl FnMut() -> Result<bool, BUSY>,
+    mut deadline: timer::Deadline,
+) -> Result<(), SpiError<SPI, BUSY, RESET, CS>> {
+    while !is_done().map_err(SpiError::Busy)? {
+        if deadline.is_expired() {
+            return Err(SpiError::BusyTimeout);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,383 @@
+use super::spi;
+use super::SpiError;
+use crate::command;
+use crate::error;
+use crate::frame;
+use crate::params;
+use crate::timer;
+use crate::types;
+use core::convert;
+use core::fmt;
+use core::time;
+use embedded_hal_1::delay::DelayNs;
+use embedded_hal_1::digital::{InputPin, OutputPin};
+use embedded_hal_1::spi::{Operation, SpiBus, SpiDevice};
+
+// How long the module is given to acknowledge the chip select where the BUSY pin can't be watched
+// in between, as the `SpiDevice` asserts the chip select only as part of the transfer
+pub(super) const ACK_DELAY_NS: u32 = 50_000;
+
+// Transfers whole frames through an `SpiDevice`, which owns the chip select line and keeps it
+// asserted for the duration of each transfer. Unlike the other SPI transports, it can't see the
+// module acknowledge the chip select, so each transfer waits `ACK_DELAY_NS` after asserting it
+// instead; prefer `SpiBusTransport` where the chip select pin is at hand.
+#[derive(Debug)]
+pub struct SpiDeviceTransport<
+    SPI,
+    BUSY,
+    RESET,
+    DELAY,
+    const N: usize = { frame::DEFAULT_FRAME_CAPACITY },
+> {
+    spi: SPI,
+    busy: BUSY,
+    reset: RESET,
+    delay: DELAY,
+    buffer: [u8; N],
+    timeouts: types::SpiTimeouts,
+    clock: Option<fn() -> time::Duration>,
+}
+
+// Transfers whole frames on an exclusively owned `SpiBus` with a separate chip select pin
+#[derive(Debug)]
+pub struct SpiBusTransport<
+    SPI,
+    BUSY,
+    RESET,
+    CS,
+    DELAY,
+    const N: usize = { frame::DEFAULT_FRAME_CAPACITY },
+> {
+    spi: SPI,
+    busy: BUSY,
+    reset: RESET,
+    cs: CS,
+    delay: DELAY,
+    buffer: [u8; N],
+    timeouts: types::SpiTimeouts,
+    clock: Option<fn() -> time::Duration>,
+}
+
+impl<SPI, BUSY, RESET, DELAY, const N: usize> super::Transport
+    for SpiDeviceTransport<SPI, BUSY, RESET, DELAY, N>
+where
+    SPI: SpiDevice,
+    BUSY: InputPin,
+    RESET: OutputPin,
+    DELAY: DelayNs,
+{
+    type Error = SpiError<SPI::Error, BUSY::Error, RESET::Error, convert::Infallible>;
+
+    #[inline]
+    fn reset(&mut self) -> Result<(), Self::Error> {
+        reset(&mut self.reset, &mut self.delay)
+    }
+
+    #[inline]
+    fn delay(&mut self, duration: time::Duration) -> Result<(), Self::Error> {
+        delay(&mut self.delay, duration);
+        Ok(())
+    }
+
+    fn resync(&mut self) -> Result<(), Self::Error> {
+        let spi = &mut self.spi;
+        let busy = &mut self.busy;
+        let (clock, timeouts) = (self.clock, self.timeouts);
+        frame::resync(&mut self.buffer, |buf| {
+            device_transfer(spi, busy, clock, timeouts, buf)
+        })
+    }
+
+    fn is_desync(error: &Self::Error) -> bool {
+        error.is_desync()
+    }
+
+    fn max_data_len(&self) -> usize {
+        frame::max_data_len(N)
+    }
+
+    fn set_clock(&mut self, now: fn() -> time::Duration) {
+        self.clock = Some(now);
+    }
+
+    fn protocol_error(error: &Self::Error) -> Option<error::ProtocolError> {
+        error.protocol_error()
+    }
+
+    #[inline]
+    fn handle_cmd<SP, RP>(
+        &mut self,
+        command: command::Command,
+        send_params: &SP,
+        recv_params: &mut RP,
+        long_send: bool,
+        long_recv: bool,
+    ) -> Result<(), Self::Error>
+    where
+        SP: params::SendParams + fmt::Debug,
+        RP: params::RecvParams + fmt::Debug,
+    {
+        let spi = &mut self.spi;
+        let busy = &mut self.busy;
+        let (clock, timeouts) = (self.clock, self.timeouts);
+        frame::handle_cmd(
+            &mut self.buffer,
+            command,
+            send_params,
+            recv_params,
+            long_send,
+            long_recv,
+            |buf| device_transfer(spi, busy, clock, timeouts, buf),
+        )
+    }
+}
+
+impl<SPI, BUSY, RESET, DELAY, const N: usize> SpiDeviceTransport<SPI, BUSY, RESET, DELAY, N>
+where
+    SPI: SpiDevice,
+    BUSY: InputPin,
+    RESET: OutputPin,
+    DELAY: DelayNs,
+{
+    // The chip select is acknowledged by waiting rather than watching the BUSY pin, see above
+    #[inline]
+    pub fn start(
+        spi: SPI,
+        busy: BUSY,
+        reset: RESET,
+        delay: DELAY,
+    ) -> Result<Self, SpiError<SPI::Error, BUSY::Error, RESET::Error, convert::Infallible>> {
+        let buffer = [0; N];
+        let mut this = Self {
+            spi,
+            busy,
+            reset,
+            delay,
+            buffer,
+            timeouts: types::SpiTimeouts::default(),
+            clock: None,
+        };
+
+        super::Transport::reset(&mut this)?;
+
+        Ok(this)
+    }
+
+    pub fn set_timeouts(&mut self, timeouts: types::SpiTimeouts) {
+        self.timeouts = timeouts;
+    }
+}
+
+impl<SPI, BUSY, RESET, CS, DELAY, const N: usize> super::Transport
+    for SpiBusTransport<SPI, BUSY, RESET, CS, DELAY, N>
+where
+    SPI: SpiBus,
+    BUSY: InputPin,
+    RESET: OutputPin,
+    CS: OutputPin,
+    DELAY: DelayNs,
+{
+    type Error = SpiError<SPI::Error, BUSY::Error, RESET::Error, CS::Error>;
+
+    #[inline]
+    fn reset(&mut self) -> Result<(), Self::Error> {
+        self.cs.set_high().map_err(SpiError::ChipSelect)?;
+        reset(&mut self.reset, &mut self.delay)
+    }
+
+    #[inline]
+    fn delay(&mut self, duration: time::Duration) -> Result<(), Self::Error> {
+        delay(&mut self.delay, duration);
+        Ok(())
+    }
+
+    fn resync(&mut self) -> Result<(), Self::Error> {
+        let spi = &mut self.spi;
+        let busy = &mut self.busy;
+        let cs = &mut self.cs;
+        let (clock, timeouts) = (self.clock, self.timeouts);
+        frame::resync(&mut self.buffer, |buf| {
+            bus_transfer(spi, busy, cs, clock, timeouts, buf)
+        })
+    }
+
+    fn is_desync(error: &Self::Error) -> bool {
+        error.is_desync()
+    }
+
+    fn max_data_len(&self) -> usize {
+        frame::max_data_len(N)
+    }
+
+    fn set_clock(&mut self, now: fn() -> time::Duration) {
+        self.clock = Some(now);
+    }
+
+    fn protocol_error(error: &Self::Error) -> Option<error::ProtocolError> {
+        error.protocol_error()
+    }
+
+    #[inline]
+    fn handle_cmd<SP, RP>(
+        &mut self,
+        command: command::Command,
+        send_params: &SP,
+        recv_params: &mut RP,
+        long_send: bool,
+        long_recv: bool,
+    ) -> Result<(), Self::Error>
+    where
+        SP: params::SendParams + fmt::Debug,
+        RP: params::RecvParams + fmt::Debug,
+    {
+        let spi = &mut self.spi;
+        let busy = &mut self.busy;
+        let cs = &mut self.cs;
+        let (clock, timeouts) = (self.clock, self.timeouts);
+        frame::handle_cmd(
+            &mut self.buffer,
+            command,
+            send_params,
+            recv_params,
+            long_send,
+            long_recv,
+            |buf| bus_transfer(spi, busy, cs, clock, timeouts, buf),
+        )
+    }
+}
+
+impl<SPI, BUSY, RESET, CS, DELAY, const N: usize> SpiBusTransport<SPI, BUSY, RESET, CS, DELAY, N>
+where
+    SPI: SpiBus,
+    BUSY: InputPin,
+    RESET: OutputPin,
+    CS: OutputPin,
+    DELAY: DelayNs,
+{
+    #[inline]
+    pub fn start(
+        spi: SPI,
+        busy: BUSY,
+        reset: RESET,
+        cs: CS,
+        delay: DELAY,
+    ) -> Result<Self, SpiError<SPI::Error, BUSY::Error, RESET::Error, CS::Error>> {
+        let buffer = [0; N];
+        let mut this = Self {
+            spi,
+            busy,
+            reset,
+            cs,
+            delay,
+            buffer,
+            timeouts: types::SpiTimeouts::default(),
+            clock: None,
+        };
+
+        super::Transport::reset(&mut this)?;
+
+        Ok(this)
+    }
+
+    pub fn set_timeouts(&mut self, timeouts: types::SpiTimeouts) {
+        self.timeouts = timeouts;
+    }
+}
+
+// Transfers a frame once the module is ready for it, giving it time to acknowledge the chip select
+fn device_transfer<SPI, BUSY, RESET>(
+    spi: &mut SPI,
+    busy: &mut BUSY,
+    clock: Option<fn() -> time::Duration>,
+    timeouts: types::SpiTimeouts,
+    buf: &mut [u8],
+) -> Result<(), SpiError<SPI::Error, BUSY::Error, RESET, convert::Infallible>>
+where
+    SPI: SpiDevice,
+    BUSY: InputPin,
+{
+    spi::await_busy(
+        || busy.is_low(),
+        timer::Deadline::start(clock, timeouts.ready, timeouts.ready_polls),
+    )?;
+
+    spi.transaction(&mut [
+        Operation::DelayNs(ACK_DELAY_NS),
+        Operation::TransferInPlace(buf),
+    ])
+    .map_err(SpiError::Spi)
+}
+
+// Transfers a frame once the module is ready for it and has acknowledged the chip select
+fn bus_transfer<SPI, BUSY, CS, RESET>(
+    spi: &mut SPI,
+    busy: &mut BUSY,
+    cs: &mut CS,
+    clock: Option<fn() -> time::Duration>,
+    timeouts: types::SpiTimeouts,
+    buf: &mut [u8],
+) -> Result<(), SpiError<SPI::Error, BUSY::Error, RESET, CS::Error>>
+where
+    SPI: SpiBus,
+    BUSY: InputPin,
+    CS: OutputPin,
+{
+    spi::await_busy(
+        || busy.is_low(),
+        timer::Deadline::start(clock, timeouts.ready, timeouts.ready_polls),
+    )?;
+
+    cs.set_low().map_err(SpiError::ChipSelect)?;
+
+    if let Err(err) = spi::await_busy(
+        || busy.is_high(),
+        timer::Deadline::start(clock, timeouts.ack, timeouts.ack_polls),
+    ) {
+        cs.set_high().map_err(SpiError::ChipSelect)?;
+        return Err(err);
+    }
+
+    let result = spi
+        .transfer_in_place(buf)
+        .and_then(|()| spi.flush())
+        .map_err(SpiError::Spi);
+
+    cs.set_high().map_err(SpiError::ChipSelect)?;
+
+    result
+}
+
+fn reset<RESET, DELAY, SPI, BUSY, CS>(
+    reset: &mut RESET,
+    delay: &mut DELAY,
+) -> Result<(), SpiError<SPI, BUSY, RESET::Error, CS>>
+where
+    RESET: OutputPin,
+    DELAY: DelayNs,
+{
+    #[cfg(feature = "reset-high")]
+    reset.set_high().map_err(SpiError::Reset)?;
+    #[cfg(not(feature = "reset-high"))]
+    reset.set_low().map_err(SpiError::Reset)?;
+
+    delay.delay_ms(10);
+
+    #[cfg(feature = "reset-high")]
+    reset.set_low().map_err(SpiError::Reset)?;
+    #[cfg(not(feature = "reset-high"))]
+    reset.set_high().map_err(SpiError::Reset)?;
+
+    delay.delay_ms(750);
+
+    Ok(())
+}
+
+fn delay<DELAY>(delay: &mut DELAY, duration: time::Duration)
+where
+    DELAY: DelayNs,
+{
+    use core::convert::TryFrom;
+
+    let us = u32::try_from(duration.as_micros()).unwrap_or(u32::MAX);
+    delay.delay_us(us);
+}
@@ -0,0 +1,451 @@
+use crate::command;
+use crate::encoding;
+use crate::error;
+use crate::frame::{END_CMD, ERR_CMD, REPLY_FLAG, START_CMD};
+use crate::params;
+use crate::timer;
+use crate::types;
+use core::fmt;
+use core::time;
+use embedded_hal::digital::v2::OutputPin;
+use embedded_hal::serial;
+
+#[derive(Debug)]
+pub struct UartTransport<TX, RX, RESET, DELAY> {
+    tx: TX,
+    rx: RX,
+    reset: RESET,
+    delay: DELAY,
+    timeouts: types::UartTimeouts,
+    clock: Option<fn() -> time::Duration>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum UartError<TX, RX, RESET> {
+    Write(TX),
+    Read(RX),
+    Reset(RESET),
+    Timeout,
+    ErrorResponse,
+    UnexpectedReplyByte(u8),
+    Protocol(error::ProtocolError),
+}
+
+// Most bytes skipped while waiting for a reply to start
+const WAIT_REPLY_TIMEOUT_BYTES: usize = 1000;
+
+struct Tx<'a, TX>(&'a mut TX);
+
+enum RxError<RX> {
+    Read(RX),
+    Timeout,
+    Protocol(error::ProtocolError),
+}
+
+struct Rx<'a, RX> {
+    rx: &'a mut RX,
+    timeouts: types::UartTimeouts,
+    clock: Option<fn() -> time::Duration>,
+}
+
+impl<TX, RX, RESET, DELAY> super::Transport for UartTransport<TX, RX, RESET, DELAY>
+where
+    TX: serial::Write<u8>,
+    RX: serial::Read<u8>,
+    RESET: OutputPin,
+    DELAY: FnMut(time::Duration),
+{
+    type Error = UartError<TX::Error, RX::Error, RESET::Error>;
+
+    #[inline]
+    fn reset(&mut self) -> Result<(), Self::Error> {
+        #[cfg(feature = "reset-high")]
+        self.reset.set_high().map_err(UartError::Reset)?;
+        #[cfg(not(feature = "reset-high"))]
+        self.reset.set_low().map_err(UartError::Reset)?;
+
+        self.delay(time::Duration::from_millis(10))?;
+
+        #[cfg(feature = "reset-high")]
+        self.reset.set_low().map_err(UartError::Reset)?;
+        #[cfg(not(feature = "reset-high"))]
+        self.reset.set_high().map_err(UartError::Reset)?;
+
+        self.delay(time::Duration::from_millis(750))?;
+
+        // Discard anything printed by the firmware while booting
+        self.drain()
+    }
+
+    #[inline]
+    fn delay(&mut self, duration: time::Duration) -> Result<(), Self::Error> {
+        (self.delay)(duration);
+        Ok(())
+    }
+
+    #[inline]
+    fn resync(&mut self) -> Result<(), Self::Error> {
+        self.drain()
+    }
+
+    fn is_desync(error: &Self::Error) -> bool {
+        matches!(
+            error,
+            UartError::Timeout
+                | UartError::ErrorResponse
+                | UartError::UnexpectedReplyByte(_)
+                | UartError::Protocol(_)
+        )
+    }
+
+    fn set_clock(&mut self, now: fn() -> time::Duration) {
+        self.clock = Some(now);
+    }
+
+    fn protocol_error(error: &Self::Error) -> Option<error::ProtocolError> {
+        match *error {
+            UartError::Protocol(error) => Some(error),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    fn handle_cmd<SP, RP>(
+        &mut self,
+        command: command::Command,
+        send_params: &SP,
+        recv_params: &mut RP,
+        long_send: bool,
+        long_recv: bool,
+    ) -> Result<(), Self::Error>
+    where
+        SP: params::SendParams + fmt::Debug,
+        RP: params::RecvParams + fmt::Debug,
+    {
+        use crate::encoding::Sink as _;
+
+        // Drop the remains of any earlier reply so that the next START_CMD belongs to ours
+        self.drain()?;
+
+        // Unlike over SPI, the frame is not padded to a 4 byte boundary
+        let mut tx = Tx(&mut self.tx);
+        tx.send_byte(START_CMD)?;
+        tx.send_byte(u8::from(command) & !REPLY_FLAG)?;
+        send_params.send(&mut tx, long_send)?;
+        tx.send_byte(END_CMD)?;
+        nb::block!(tx.0.flush()).map_err(UartError::Write)?;
+        log::debug!("send {:?} {:?}", command, send_params);
+
+        let mut rx = Rx {
+            rx: &mut self.rx,
+            timeouts: self.timeouts,
+            clock: self.clock,
+        };
+        rx.await_start_cmd()?;
+        rx.expect_byte(u8::from(command) | REPLY_FLAG)?;
+        recv_params.recv(&mut rx, long_recv)?;
+        rx.expect_byte(END_CMD)?;
+        log::debug!("recv {:?} {:?} -> {:?}", command, send_params, recv_params);
+
+        Ok(())
+    }
+}
+
+impl<TX, RX, RESET, DELAY> UartTransport<TX, RX, RESET, DELAY>
+where
+    TX: serial::Write<u8>,
+    RX: serial::Read<u8>,
+    RESET: OutputPin,
+    DELAY: FnMut(time::Duration),
+{
+    #[inline]
+    pub fn start(
+        tx: TX,
+        rx: RX,
+        reset: RESET,
+        delay: DELAY,
+    ) -> Result<Self, UartError<TX::Error, RX::Error, RESET::Error>> {
+        let mut this = Self {
+            tx,
+            rx,
+            reset,
+            delay,
+            timeouts: types::UartTimeouts::default(),
+            clock: None,
+        };
+
+        super::Transport::reset(&mut this)?;
+
+        Ok(this)
+    }
+
+    pub fn set_timeouts(&mut self, timeouts: types::UartTimeouts) {
+        self.timeouts = timeouts;
+    }
+
+    #[inline]
+    fn drain(&mut self) -> Result<(), UartError<TX::Error, RX::Error, RESET::Error>> {
+        loop {
+            match self.rx.read() {
+                Ok(byte) => log::trace!("drop {:#04x}", byte),
+                Err(nb::Error::WouldBlock) => return Ok(()),
+                Err(nb::Error::Other(err)) => return Err(UartError::Read(err)),
+            }
+        }
+    }
+}
+
+impl<TX> encoding::Sink for Tx<'_, TX>
+where
+    TX: serial::Write<u8>,
+{
+    type Error = encoding::LinkError<TX::Error>;
+
+    #[inline]
+    fn send_byte(&mut self, byte: u8) -> Result<(), Self::Error> {
+        nb::block!(self.0.write(byte)).map_err(encoding::LinkError::Link)?;
+        log::trace!("send {:#04x}", byte);
+        Ok(())
+    }
+}
+
+impl<RX> Rx<'_, RX>
+where
+    RX: serial::Read<u8>,
+{
+    #[inline]
+    fn recv_byte_within(
+        &mut self,
+        deadline: &mut timer::Deadline,
+    ) -> Result<u8, RxError<RX::Error>> {
+        loop {
+            match self.rx.read() {
+                Ok(byte) => {
+                    log::trace!("recv {:#04x}", byte);
+                    return Ok(byte);
+                }
+                Err(nb::Error::WouldBlock) if deadline.is_expired() => {
+                    return Err(RxError::Timeout)
+                }
+                Err(nb::Error::WouldBlock) => {}
+                Err(nb::Error::Other(err)) => return Err(RxError::Read(err)),
+            }
+        }
+    }
+
+    #[inline]
+    fn await_start_cmd<TX, RESET>(&mut self) -> Result<(), UartError<TX, RX::Error, RESET>> {
+        // One deadline for the whole wait, so that a stream of garbage can't extend it
+        let mut deadline =
+            timer::Deadline::start(self.clock, self.timeouts.reply, self.timeouts.reply_polls);
+        for _ in 0..=WAIT_REPLY_TIMEOUT_BYTES {
+            let byte = self.recv_byte_within(&mut deadline)?;
+            if byte == ERR_CMD {
+                return Err(UartError::ErrorResponse);
+            }
+            if byte == START_CMD {
+                return Ok(());
+            }
+        }
+        Err(UartError::Timeout)
+    }
+
+    #[inline]
+    fn expect_byte<TX, RESET>(
+        &mut self,
+        expected_byte: u8,
+    ) -> Result<(), UartError<TX, RX::Error, RESET>> {
+        let byte = encoding::Source::recv_byte(self)?;
+        if byte == expected_byte {
+            Ok(())
+        } else {
+            Err(UartError::UnexpectedReplyByte(byte))
+        }
+    }
+}
+
+impl<RX> encoding::Source for Rx<'_, RX>
+where
+    RX: serial::Read<u8>,
+{
+    type Error = RxError<RX::Error>;
+
+    #[inline]
+    fn recv_byte(&mut self) -> Result<u8, Self::Error> {
+        let mut deadline =
+            timer::Deadline::start(self.clock, self.timeouts.byte, self.timeouts.byte_polls);
+        self.recv_byte_within(&mut deadline)
+    }
+}
+
+impl<TX, RX, RESET> From<RxError<RX>> for UartError<TX, RX, RESET> {
+    fn from(error: RxError<RX>) -> Self {
+        match error {
+            RxError::Read(err) => UartError::Read(err),
+            RxError::Timeout => UartError::Timeout,
+            RxError::Protocol(error) => UartError::Protocol(error),
+        }
+    }
+}
+
+impl<TX, RX, RESET> From<encoding::LinkError<TX>> for UartError<TX, RX, RESET> {
+    fn from(error: encoding::LinkError<TX>) -> Self {
+        match error {
+            encoding::LinkError::Link(err) => UartError::Write(err),
+            encoding::LinkError::Protocol(error) => UartError::Protocol(error),
+        }
+    }
+}
+
+impl<RX> From<error::ProtocolError> for RxError<RX> {
+    fn from(error: error::ProtocolError) -> Self {
+        RxError::Protocol(error)
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::{UartError, UartTransport};
+    use crate::command::Command;
+    use crate::error::Error;
+    use crate::frame::{END_CMD, ERR_CMD, REPLY_FLAG, START_CMD};
+    use crate::types::UartTimeouts;
+    use core::cell::RefCell;
+    use core::convert;
+    use core::time;
+    use embedded_hal::serial;
+    use std::collections::VecDeque;
+    use std::rc::Rc;
+    use std::vec;
+    use std::vec::Vec;
+
+    // A serial port that answers each flushed command with the next scripted reply
+    #[derive(Default)]
+    struct Script {
+        replies: VecDeque<Vec<u8>>,
+        written: Vec<u8>,
+        pending: VecDeque<u8>,
+    }
+
+    struct ScriptedTx(Rc<RefCell<Script>>);
+
+    struct ScriptedRx(Rc<RefCell<Script>>);
+
+    struct NoReset;
+
+    impl serial::Write<u8> for ScriptedTx {
+        type Error = convert::Infallible;
+
+        fn write(&mut self, byte: u8) -> nb::Result<(), Self::Error> {
+            self.0.borrow_mut().written.push(byte);
+            Ok(())
+        }
+
+        fn flush(&mut self) -> nb::Result<(), Self::Error> {
+            let mut script = self.0.borrow_mut();
+            if let Some(reply) = script.replies.pop_front() {
+                script.pending.extend(reply);
+            }
+            Ok(())
+        }
+    }
+
+    impl serial::Read<u8> for ScriptedRx {
+        type Error = convert::Infallible;
+
+        fn read(&mut self) -> nb::Result<u8, Self::Error> {
+            self.0
+                .borrow_mut()
+                .pending
+                .pop_front()
+                .ok_or(nb::Error::WouldBlock)
+        }
+    }
+
+    impl embedded_hal::digital::v2::OutputPin for NoReset {
+        type Error = convert::Infallible;
+
+        fn set_low(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    type ScriptedWifi =
+        crate::Wifi<UartTransport<ScriptedTx, ScriptedRx, NoReset, fn(time::Duration)>>;
+
+    fn wifi(replies: &[&[u8]]) -> (ScriptedWifi, Rc<RefCell<Script>>) {
+        let script = Rc::new(RefCell::new(Script {
+            replies: replies.iter().map(|reply| reply.to_vec()).collect(),
+            ..Script::default()
+        }));
+        let mut transport = UartTransport::start(
+            ScriptedTx(script.clone()),
+            ScriptedRx(script.clone()),
+            NoReset,
+            (|_| {}) as fn(time::Duration),
+        )
+        .unwrap();
+        transport.set_timeouts(UartTimeouts {
+            reply_polls: 10,
+            byte_polls: 10,
+            ..UartTimeouts::default()
+        });
+        (crate::Wifi::new(transport), script)
+    }
+
+    #[test]
+    fn handle_cmd() {
+        let command = u8::from(Command::GetFwVersionCmd);
+        let mut reply = vec![START_CMD, command | REPLY_FLAG, 1, 6];
+        reply.extend_from_slice(b"1.7.4\0");
+        reply.push(END_CMD);
+        let (mut wifi, script) = wifi(&[&reply]);
+
+        assert_eq!(&wifi.get_firmware_version().unwrap()[..], b"1.7.4");
+        // START_CMD, command, param count, length, param and END_CMD, without padding
+        assert_eq!(
+            script.borrow().written,
+            vec![START_CMD, command, 1, 1, 0, END_CMD]
+        );
+    }
+
+    #[test]
+    fn error_response() {
+        let (mut wifi, _) = wifi(&[&[ERR_CMD]]);
+
+        assert_eq!(wifi.rssi(), Err(Error::Transport(UartError::ErrorResponse)));
+    }
+
+    #[test]
+    fn unexpected_reply_byte() {
+        // A reply to another command
+        let command = u8::from(Command::GetCurrRssiCmd) + 1;
+        let (mut wifi, _) = wifi(&[&[START_CMD, command | REPLY_FLAG, 1, 1, 3, END_CMD]]);
+
+        assert_eq!(
+            wifi.rssi(),
+            Err(Error::Transport(UartError::UnexpectedReplyByte(
+                command | REPLY_FLAG
+            )))
+        );
+    }
+
+    #[test]
+    fn timeout() {
+        let (mut wifi, _) = wifi(&[]);
+
+        assert_eq!(wifi.rssi(), Err(Error::Transport(UartError::Timeout)));
+    }
+
+    #[test]
+    fn reply_cut_short() {
+        let command = u8::from(Command::GetCurrRssiCmd);
+        let (mut wifi, _) = wifi(&[&[START_CMD, command | REPLY_FLAG, 1, 4, 0]]);
+
+        assert_eq!(wifi.rssi(), Err(Error::Transport(UartError::Timeout)));
+    }
+}
@@ -1,4 +1,5 @@
 use core::fmt;
+use core::time;
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum Config<'a> {
@@ -9,6 +10,8 @@ pub enum Config<'a> {
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct StationConfig<'a> {
     pub network: NetworkConfig<'a>,
+    // A static configuration to use instead of DHCP
+    pub ip_config: Option<IpConfig>,
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -18,15 +21,84 @@ pub enum NetworkConfig<'a> {
     // TODO: WPA2 enterprise etc
 }
 
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct IpConfig {
+    pub ip: no_std_net::Ipv4Addr,
+    pub gateway: no_std_net::Ipv4Addr,
+    pub mask: no_std_net::Ipv4Addr,
+    pub dns_server1: Option<no_std_net::Ipv4Addr>,
+    pub dns_server2: Option<no_std_net::Ipv4Addr>,
+}
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct AccessPointConfig<'a> {
-    pub ssid: &'a [u8],
-    pub password: &'a [u8],
+    pub network: NetworkConfig<'a>,
+    pub channel: u8,
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct RecoveryConfig {
+    // How many times to retry an idempotent command after the transport lost sync
+    pub retries: u8,
+    // Reset the module after this many consecutive failed commands
+    pub reset_after: Option<u8>,
+}
+
+// How long the SPI transports wait on the module before giving up with a timeout
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SpiTimeouts {
+    // For the module to finish the previous command, e.g. while connecting a client
+    pub ready: time::Duration,
+    // For the module to acknowledge the chip select
+    pub ack: time::Duration,
+    // For a reply to start once it is being read, where replies are read byte by byte rather than
+    // as whole frames
+    pub reply: time::Duration,
+    // How many times the above are polled instead when no clock is set
+    pub ready_polls: u32,
+    pub ack_polls: u32,
+    pub reply_polls: u32,
+}
+
+// How long the UART transport waits on the module before giving up with a timeout
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct UartTimeouts {
+    // For a reply to start, e.g. while connecting a client
+    pub reply: time::Duration,
+    // Between two bytes of the same reply
+    pub byte: time::Duration,
+    // How many times the above are polled instead when no clock is set
+    pub reply_polls: u32,
+    pub byte_polls: u32,
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub struct Socket(pub(crate) u8);
 
+// What a `Wifi` knows about one of the sockets it handed out
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SocketInfo {
+    pub socket: Socket,
+    pub kind: SocketKind,
+    pub protocol: Option<ProtocolMode>,
+    pub state: SocketState,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SocketKind {
+    Client,
+    Server,
+    Udp,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SocketState {
+    Allocated,
+    Connected,
+    Listening,
+    Bound,
+}
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct ScannedNetwork {
     pub ssid: arrayvec::ArrayVec<[u8; 32]>,
@@ -104,10 +176,40 @@ pub struct NetworkData {
     pub gateway: no_std_net::Ipv4Addr,
 }
 
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct MacAddress(pub [u8; 6]);
+
 #[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
 pub struct RemoteData {
     pub ip: no_std_net::Ipv4Addr,
-    pub port: u32,
+    pub port: u16,
+}
+
+impl Default for SpiTimeouts {
+    fn default() -> Self {
+        Self {
+            ready: time::Duration::from_secs(10),
+            ack: time::Duration::from_millis(100),
+            reply: time::Duration::from_millis(10),
+            // Roughly the durations at ten million polls a second, apart from the reply, which is
+            // polled once per byte read over SPI
+            ready_polls: 100_000_000,
+            ack_polls: 1_000_000,
+            reply_polls: 1000,
+        }
+    }
+}
+
+impl Default for UartTimeouts {
+    fn default() -> Self {
+        Self {
+            reply: time::Duration::from_secs(10),
+            byte: time::Duration::from_millis(100),
+            // Roughly the durations at ten million polls a second
+            reply_polls: 100_000_000,
+            byte_polls: 1_000_000,
+        }
+    }
 }
 
 impl fmt::Display for ScannedNetwork {
@@ -137,6 +239,45 @@ impl fmt::Display for ScannedNetwork {
     }
 }
 
+impl Config<'_> {
+    // Whether the module has joined the network of a station config, or opened the access point
+    pub(crate) fn is_up(&self, state: ConnectionState) -> bool {
+        match self {
+            Config::Station(_) => state == ConnectionState::Connected,
+            Config::AccessPoint(_) => {
+                state == ConnectionState::ApListening || state == ConnectionState::ApConnected
+            }
+        }
+    }
+}
+
+impl NetworkData {
+    pub fn prefix_len(&self) -> u8 {
+        u32::from(self.mask).leading_ones() as u8
+    }
+}
+
+impl TcpState {
+    // Whether the peer has closed its side, so no more data will arrive
+    pub fn is_peer_closed(self) -> bool {
+        matches!(
+            self,
+            TcpState::Closed
+                | TcpState::CloseWait
+                | TcpState::Closing
+                | TcpState::LastAck
+                | TcpState::TimeWait
+        )
+    }
+}
+
+impl fmt::Display for MacAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use itertools::Itertools;
+        write!(f, "{:02x}", self.0.iter().format(":"))
+    }
+}
+
 impl fmt::Display for EncryptionType {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let string = match *self {
@@ -0,0 +1,199 @@
+// Drives `Wifi` through the `SimTransport`, against servers and peers on the loopback interface
+
+use no_std_net::Ipv4Addr;
+use std::io::{Read as _, Write as _};
+use std::net;
+use std::thread;
+use std::time::Duration;
+use wifi_nina::transport::SimTransport;
+use wifi_nina::types::ProtocolMode;
+use wifi_nina::{Error, Wifi};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+fn wifi() -> Wifi<SimTransport> {
+    let mut wifi = Wifi::new(SimTransport::new());
+    wifi.set_poll_interval(Duration::from_millis(1));
+    wifi
+}
+
+// Serves a single connection, echoing everything back until the peer closes it
+fn echo_server() -> u16 {
+    let listener = net::TcpListener::bind((net::Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let port = listener.local_addr().unwrap().port();
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut buf = [0; 1024];
+        loop {
+            match stream.read(&mut buf).unwrap() {
+                0 => break,
+                len => stream.write_all(&buf[..len]).unwrap(),
+            }
+        }
+    });
+    port
+}
+
+fn free_port() -> u16 {
+    let listener = net::TcpListener::bind((net::Ipv4Addr::LOCALHOST, 0)).unwrap();
+    listener.local_addr().unwrap().port()
+}
+
+#[test]
+fn unconnected_client_holds_back_the_next_socket() {
+    let port = echo_server();
+    let mut wifi = wifi();
+
+    // The firmware hands out the same socket until it is started
+    let mut first = wifi.new_client().unwrap();
+    assert_eq!(
+        wifi.new_client().err(),
+        Some(Error::SocketInUse(first.socket()))
+    );
+
+    first
+        .connect_ipv4(&mut wifi, Ipv4Addr::localhost(), port, ProtocolMode::Tcp)
+        .unwrap();
+    let second = wifi.new_client().unwrap();
+    assert_ne!(first.socket(), second.socket());
+    assert_eq!(wifi.sockets().count(), 2);
+}
+
+#[test]
+fn tcp_round_trip() {
+    let port = echo_server();
+    let mut wifi = wifi();
+
+    let mut client = wifi.new_client().unwrap();
+    client
+        .connect_ipv4(&mut wifi, Ipv4Addr::localhost(), port, ProtocolMode::Tcp)
+        .unwrap();
+    client.send_all(&mut wifi, b"hello, nina").unwrap();
+
+    let mut reply = [0; 11];
+    client
+        .recv_exact_timeout(&mut wifi, &mut reply, TIMEOUT)
+        .unwrap();
+    assert_eq!(&reply, b"hello, nina");
+
+    client.close(&mut wifi).unwrap();
+    assert_eq!(wifi.sockets().count(), 0);
+}
+
+#[test]
+fn tcp_large_transfer() {
+    let port = echo_server();
+    let mut wifi = wifi();
+    let data = (0..10_000).map(|i| i as u8).collect::<Vec<_>>();
+
+    let mut client = wifi.new_client().unwrap();
+    client
+        .connect_ipv4(&mut wifi, Ipv4Addr::localhost(), port, ProtocolMode::Tcp)
+        .unwrap();
+    client.send_all(&mut wifi, &data).unwrap();
+
+    // Larger than the client's buffer, so reads go straight into it
+    let mut reply = vec![0; data.len()];
+    client
+        .recv_exact_timeout(&mut wifi, &mut reply, TIMEOUT)
+        .unwrap();
+    assert_eq!(reply, data);
+}
+
+#[test]
+fn tcp_connect_hostname() {
+    let port = echo_server();
+    let mut wifi = wifi();
+    wifi.transport_mut()
+        .add_host("echo.test", net::Ipv4Addr::LOCALHOST);
+
+    let mut client = wifi.new_client().unwrap();
+    client
+        .connect_hostname(&mut wifi, "echo.test", port, ProtocolMode::Tcp)
+        .unwrap();
+    client.send_all(&mut wifi, b"ping").unwrap();
+
+    let mut reply = [0; 4];
+    client
+        .recv_exact_timeout(&mut wifi, &mut reply, TIMEOUT)
+        .unwrap();
+    assert_eq!(&reply, b"ping");
+}
+
+#[test]
+fn tcp_server_accept() {
+    let port = free_port();
+    let mut wifi = wifi();
+
+    let mut server = wifi.new_server().unwrap();
+    server.listen(&mut wifi, port, ProtocolMode::Tcp).unwrap();
+
+    let mut peer = net::TcpStream::connect((net::Ipv4Addr::LOCALHOST, port)).unwrap();
+    peer.write_all(b"from peer").unwrap();
+
+    let mut client = nb::block!(server.accept(&mut wifi)).unwrap();
+    let mut data = [0; 9];
+    client
+        .recv_exact_timeout(&mut wifi, &mut data, TIMEOUT)
+        .unwrap();
+    assert_eq!(&data, b"from peer");
+
+    client.send_all(&mut wifi, b"from nina").unwrap();
+    let mut reply = [0; 9];
+    peer.read_exact(&mut reply).unwrap();
+    assert_eq!(&reply, b"from nina");
+}
+
+#[test]
+fn udp_round_trip() {
+    let peer = net::UdpSocket::bind((net::Ipv4Addr::LOCALHOST, 0)).unwrap();
+    peer.set_read_timeout(Some(TIMEOUT)).unwrap();
+    let peer_port = peer.local_addr().unwrap().port();
+    let mut wifi = wifi();
+
+    let mut socket = wifi.new_udp_socket().unwrap();
+    socket
+        .send_to(&mut wifi, b"datagram", Ipv4Addr::localhost(), peer_port)
+        .unwrap();
+
+    let mut data = [0; 16];
+    let (len, remote) = peer.recv_from(&mut data).unwrap();
+    assert_eq!(&data[..len], b"datagram");
+    peer.send_to(b"reply", remote).unwrap();
+
+    let (len, remote_data) = loop {
+        match socket.recv_from(&mut wifi, &mut data) {
+            Err(nb::Error::WouldBlock) => thread::sleep(Duration::from_millis(1)),
+            result => break result.unwrap(),
+        }
+    };
+    assert_eq!(&data[..len], b"reply");
+    assert_eq!(remote_data.port, peer_port);
+}
+
+#[test]
+fn udp_short_buffer_discards_rest_of_datagram() {
+    let peer = net::UdpSocket::bind((net::Ipv4Addr::LOCALHOST, 0)).unwrap();
+    peer.set_read_timeout(Some(TIMEOUT)).unwrap();
+    let peer_port = peer.local_addr().unwrap().port();
+    let mut wifi = wifi();
+
+    let mut socket = wifi.new_udp_socket().unwrap();
+    socket
+        .send_to(&mut wifi, b"hello", Ipv4Addr::localhost(), peer_port)
+        .unwrap();
+    let (_, remote) = peer.recv_from(&mut [0; 16]).unwrap();
+    peer.send_to(b"first datagram", remote).unwrap();
+    peer.send_to(b"second", remote).unwrap();
+
+    let mut data = [0; 5];
+    for expected in [b"first", b"secon"] {
+        let (len, _) = loop {
+            match socket.recv_from(&mut wifi, &mut data) {
+                Err(nb::Error::WouldBlock) => thread::sleep(Duration::from_millis(1)),
+                result => break result.unwrap(),
+            }
+        };
+        assert_eq!(&data[..len], expected);
+    }
+}